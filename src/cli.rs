@@ -0,0 +1,298 @@
+use remu386::{Register, TraceLevel, MEMORY_SIZE};
+
+pub const USAGE: &str = "  --trace LEVEL        off, instructions, decode or memory (default: $REMU386_TRACE or off)
+  --debug              start in the interactive debugger
+  --gdb ADDR           wait for gdb on a TCP port, HOST:PORT or Unix socket path
+  --memory-size SIZE   guest memory in bytes, K or M suffix allowed (default: 1M)
+  --load-address ADDR  where a raw FILE is loaded (default: 0)
+  --entry ADDR         initial EIP (default: the ELF entry or the load address)
+  --stack ADDR         initial ESP (default: the top of memory)
+  --reg NAME=VALUE     initial value of a general register, repeatable
+  --load FILE@ADDR     copy a raw file to ADDR before launch, repeatable
+  --exit-address ADDR  `ret` to ADDR exits with EAX; stored at [ESP] for raw images
+                       (default: 0, or none to disable)
+  --exit-port PORT     an OUT to PORT exits with the value written
+  --dump ADDR:LEN      hexdump LEN bytes at ADDR on exit instead of the default
+                       words, repeatable
+  --dump-raw FILE[@ADDR:LEN]
+                       write guest memory, or LEN bytes at ADDR, to FILE on exit
+  --json FILE          write the final state as JSON to FILE
+  --json-memory ADDR:LEN
+                       include LEN bytes at ADDR in the JSON, repeatable
+The exit status is the low 8 bits of the guest's exit code, or of EAX after HLT.
+A run that stops before the guest exits (a breakpoint, a step limit, or quitting or
+detaching the debugger or gdb) exits with status 253. An emulator error exits with
+status 255. A host-side failure exits with status 254: a FILE that can't be read or
+loaded, a bad stack, a failed gdb connection or I/O error. A guest exit code of 253,
+254 or 255 can't be told apart from these.
+Numbers are decimal or 0x hex. ELF files are loaded at their own addresses.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub trace_level: Option<TraceLevel>,
+    pub debug: bool,
+    pub gdb: Option<String>,
+    pub memory_size: u32,
+    pub load_address: u32,
+    pub entry: Option<u32>,
+    pub stack: Option<u32>,
+    pub registers: Vec<(Register, u32)>,
+    pub loads: Vec<(String, u32)>,
+    pub exit_address: Option<u32>,
+    pub exit_port: Option<u16>,
+    pub dumps: Vec<(u32, u32)>,
+    pub raw_dumps: Vec<(String, Option<(u32, u32)>)>,
+    pub json: Option<String>,
+    pub json_memory: Vec<(u32, u32)>,
+    pub file: Option<String>
+}
+
+// decimal, or hex with a 0x prefix
+pub fn parse_number(text: &str) -> Result<u32, String> {
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse()
+    };
+    return value.map_err(|_| format!("invalid number: {}", text));
+}
+
+// a number with an optional K or M suffix
+fn parse_size(text: &str) -> Result<u32, String> {
+    let (digits, unit) = match text.chars().last() {
+        Some('k') | Some('K') => (&text[..text.len() - 1], 1024),
+        Some('m') | Some('M') => (&text[..text.len() - 1], 1024 * 1024),
+        _ => (text, 1)
+    };
+    let size = parse_number(digits)?.checked_mul(unit);
+    return match size {
+        // room for at least the exit address at the top of the stack
+        Some(size) if size >= 4 => Ok(size),
+        _ => Err(format!("invalid memory size: {}", text))
+    };
+}
+
+fn parse_register(text: &str) -> Result<(Register, u32), String> {
+    let mut parts = text.splitn(2, '=');
+    let name = parts.next().unwrap_or("");
+    let value = match parts.next() {
+        Some(value) => parse_number(value)?,
+        None => return Err(format!("expected NAME=VALUE: {}", text))
+    };
+    return Ok((name.parse()?, value));
+}
+
+fn parse_load(text: &str) -> Result<(String, u32), String> {
+    return match text.rfind('@') {
+        Some(index) if index > 0 => Ok((text[..index].to_string(), parse_number(&text[index + 1..])?)),
+        _ => Err(format!("expected FILE@ADDR: {}", text))
+    };
+}
+
+// ADDR:LEN
+fn parse_range(text: &str) -> Result<(u32, u32), String> {
+    let mut parts = text.splitn(2, ':');
+    return match (parts.next(), parts.next()) {
+        (Some(address), Some(length)) => Ok((parse_number(address)?, parse_number(length)?)),
+        _ => Err(format!("expected ADDR:LEN: {}", text))
+    };
+}
+
+// FILE or FILE@ADDR:LEN
+fn parse_raw_dump(text: &str) -> Result<(String, Option<(u32, u32)>), String> {
+    return match text.rfind('@') {
+        Some(index) if index > 0 => Ok((text[..index].to_string(), Some(parse_range(&text[index + 1..])?))),
+        Some(_) => Err(format!("expected FILE[@ADDR:LEN]: {}", text)),
+        None => Ok((text.to_string(), None))
+    };
+}
+
+// Parses the arguments after the program name.
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        trace_level: None,
+        debug: false,
+        gdb: None,
+        memory_size: MEMORY_SIZE,
+        load_address: 0,
+        entry: None,
+        stack: None,
+        registers: Vec::new(),
+        loads: Vec::new(),
+        exit_address: Some(0),
+        exit_port: None,
+        dumps: Vec::new(),
+        raw_dumps: Vec::new(),
+        json: None,
+        json_memory: Vec::new(),
+        file: None
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        // --name=value and --name value are both accepted
+        let (name, inline) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (&arg[..index], Some(arg[index + 1..].to_string())),
+            _ => (arg.as_str(), None)
+        };
+        let takes_value = ["--trace", "-t", "--gdb", "--memory-size", "--load-address", "--entry",
+                           "--stack", "--reg", "--load", "--exit-address", "--exit-port",
+                           "--dump", "--dump-raw", "--json", "--json-memory"].contains(&name);
+        let value = if !takes_value {
+            String::new()
+        } else if let Some(value) = inline {
+            value
+        } else {
+            match iter.next() {
+                Some(value) => value.clone(),
+                None => return Err(format!("missing value for {}", name))
+            }
+        };
+
+        if name == "--trace" || name == "-t" {
+            options.trace_level = Some(value.parse()?);
+        } else if name == "--debug" || name == "-d" {
+            options.debug = true;
+        } else if name == "--gdb" {
+            options.gdb = Some(value);
+        } else if name == "--memory-size" {
+            options.memory_size = parse_size(&value)?;
+        } else if name == "--load-address" {
+            options.load_address = parse_number(&value)?;
+        } else if name == "--entry" {
+            options.entry = Some(parse_number(&value)?);
+        } else if name == "--stack" {
+            options.stack = Some(parse_number(&value)?);
+        } else if name == "--reg" {
+            options.registers.push(parse_register(&value)?);
+        } else if name == "--load" {
+            options.loads.push(parse_load(&value)?);
+        } else if name == "--exit-address" {
+            options.exit_address = if value == "none" { None } else { Some(parse_number(&value)?) };
+        } else if name == "--exit-port" {
+            match parse_number(&value)? {
+                port if port <= 0xffff => options.exit_port = Some(port as u16),
+                _ => return Err(format!("invalid port: {}", value))
+            }
+        } else if name == "--dump" {
+            options.dumps.push(parse_range(&value)?);
+        } else if name == "--dump-raw" {
+            options.raw_dumps.push(parse_raw_dump(&value)?);
+        } else if name == "--json" {
+            options.json = Some(value);
+        } else if name == "--json-memory" {
+            options.json_memory.push(parse_range(&value)?);
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option: {}", arg));
+        } else if options.file.is_some() {
+            return Err(format!("unexpected argument: {}", arg));
+        } else {
+            options.file = Some(arg.clone());
+        }
+    }
+    if options.file.is_none() && options.loads.is_empty() {
+        return Err("no FILE or --load given".to_string());
+    }
+    if options.debug && options.gdb.is_some() {
+        return Err("--debug and --gdb cannot be used together".to_string());
+    }
+    return Ok(options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_size};
+    use remu386::Register::*;
+    use remu386::{TraceLevel, MEMORY_SIZE};
+
+    fn args(text: &str) -> Vec<String> {
+        return text.split_whitespace().map(|s| s.to_string()).collect();
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&args("test.bin")).unwrap();
+        assert_eq!(options.file, Some("test.bin".to_string()));
+        assert_eq!(options.memory_size, MEMORY_SIZE);
+        assert_eq!(options.load_address, 0);
+        assert_eq!(options.entry, None);
+        assert_eq!(options.trace_level, None);
+        assert!(options.loads.is_empty());
+        assert_eq!(options.exit_address, Some(0));
+        assert_eq!(options.exit_port, None);
+    }
+
+    #[test]
+    fn exit_options() {
+        let options = parse(&args("--exit-address 0xfffffff0 --exit-port 0xf4 a")).unwrap();
+        assert_eq!(options.exit_address, Some(0xfffffff0));
+        assert_eq!(options.exit_port, Some(0xf4));
+        let options = parse(&args("--exit-address=none a")).unwrap();
+        assert_eq!(options.exit_address, None);
+        assert_eq!(parse(&args("--exit-port 0x10000 a")), Err("invalid port: 0x10000".to_string()));
+    }
+
+    #[test]
+    fn dump_options() {
+        let options = parse(&args("--dump 0x100:0x40 --dump 0:16 --dump-raw mem.bin \
+                                   --dump-raw part.bin@0x200:32 a")).unwrap();
+        assert_eq!(options.dumps, vec![(0x100, 0x40), (0, 16)]);
+        assert_eq!(options.raw_dumps, vec![("mem.bin".to_string(), None),
+                                           ("part.bin".to_string(), Some((0x200, 32)))]);
+        assert_eq!(parse(&args("--dump-raw x@0x200 a")), Err("expected ADDR:LEN: 0x200".to_string()));
+        assert_eq!(parse(&args("--dump-raw @0:1 a")), Err("expected FILE[@ADDR:LEN]: @0:1".to_string()));
+    }
+
+    #[test]
+    fn json_options() {
+        let options = parse(&args("--json out.json --json-memory 0x100:16 --json-memory=0:4 a")).unwrap();
+        assert_eq!(options.json, Some("out.json".to_string()));
+        assert_eq!(options.json_memory, vec![(0x100, 16), (0, 4)]);
+        assert_eq!(parse(&args("--json-memory 0x100 a")), Err("expected ADDR:LEN: 0x100".to_string()));
+    }
+
+    #[test]
+    fn layout_options() {
+        let options = parse(&args("--memory-size 4M --load-address=0x7c00 --entry 0x7c10 --stack 0x9000 \
+                                   --reg eax=1 --reg=EBX=0x20 --load data.bin@0x8000 --load a@b@16 \
+                                   --trace insn boot.bin")).unwrap();
+        assert_eq!(options.memory_size, 4 * 1024 * 1024);
+        assert_eq!(options.load_address, 0x7c00);
+        assert_eq!(options.entry, Some(0x7c10));
+        assert_eq!(options.stack, Some(0x9000));
+        assert_eq!(options.registers, vec![(EAX, 1), (EBX, 0x20)]);
+        assert_eq!(options.loads, vec![("data.bin".to_string(), 0x8000), ("a@b".to_string(), 16)]);
+        assert_eq!(options.trace_level, Some(TraceLevel::Instructions));
+        assert_eq!(options.file, Some("boot.bin".to_string()));
+    }
+
+    #[test]
+    fn load_without_file() {
+        let options = parse(&args("--load code.bin@0")).unwrap();
+        assert_eq!(options.file, None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse(&args("")), Err("no FILE or --load given".to_string()));
+        assert_eq!(parse(&args("--entry")), Err("missing value for --entry".to_string()));
+        assert_eq!(parse(&args("--entry 12z a")), Err("invalid number: 12z".to_string()));
+        assert_eq!(parse(&args("--reg eip=1 a")), Err("unknown register: eip".to_string()));
+        assert_eq!(parse(&args("--reg eax a")), Err("expected NAME=VALUE: eax".to_string()));
+        assert_eq!(parse(&args("--load code.bin a")), Err("expected FILE@ADDR: code.bin".to_string()));
+        assert_eq!(parse(&args("--bogus a")), Err("unknown option: --bogus".to_string()));
+        assert_eq!(parse(&args("a b")), Err("unexpected argument: b".to_string()));
+        assert_eq!(parse(&args("--debug --gdb 1234 a")),
+                   Err("--debug and --gdb cannot be used together".to_string()));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64k"), Ok(64 * 1024));
+        assert_eq!(parse_size("0x100"), Ok(0x100));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("3").is_err());
+        assert_eq!(parse_size("4"), Ok(4));
+        assert!(parse_size("8192M").is_err());
+    }
+}
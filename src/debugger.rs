@@ -0,0 +1,277 @@
+use std::io::{self, BufRead, Write};
+
+use crate::emulator::{Emulator, Register, StopReason};
+use crate::emulator::Register::*;
+
+const REGISTERS: [(&str, Register); 8] = [
+    ("EAX", EAX), ("ECX", ECX), ("EDX", EDX), ("EBX", EBX),
+    ("ESP", ESP), ("EBP", EBP), ("ESI", ESI), ("EDI", EDI)
+];
+
+const HELP: &str = "\
+step [N]          (s) execute N instructions (default 1)
+continue          (c) run until a breakpoint or the program stops
+break ADDR        (b) set a breakpoint
+delete ADDR       (d) remove a breakpoint
+info              (i) list breakpoints
+regs              (r) show registers
+set REG VALUE         change EAX..EDI, EIP or EFLAGS
+x ADDR [COUNT]        examine COUNT bytes of memory (default 64)
+write ADDR BYTE...(w) write bytes to memory
+disas [ADDR] [N]  (u) disassemble N instructions (default EIP, 8)
+quit              (q) leave the debugger
+An empty line repeats the previous command. Numbers are decimal or 0x hex.";
+
+// decimal, or hex with a 0x prefix
+fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return u32::from_str_radix(hex, 16).ok();
+    }
+    return text.parse().ok();
+}
+
+// Runs the command loop until the program stops or the user quits.
+// Returns the reason the program stopped, or None after `quit`.
+pub fn run<R: BufRead, W: Write>(emu: &mut Emulator, mut input: R, mut output: W)
+                                 -> io::Result<Option<StopReason>> {
+    let mut last = String::new();
+    show_next(emu, &mut output)?;
+    loop {
+        write!(output, "(remu386) ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        let command = if line.is_empty() { last.clone() } else { line.to_string() };
+        last = command.clone();
+
+        let words: Vec<&str> = command.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let args = &words[1..];
+        let name = words[0];
+        if name == "q" || name == "quit" {
+            return Ok(None);
+        } else if name == "s" || name == "step" {
+            let count = match args.first() {
+                Some(text) => match parse_number(text) {
+                    Some(count) => count,
+                    None => {
+                        writeln!(output, "bad count: {}", text)?;
+                        continue;
+                    }
+                },
+                None => 1
+            };
+            for _ in 0..count {
+                match emu.step() {
+                    Ok(None) => {}
+                    Ok(Some(reason)) => {
+                        writeln!(output, "stopped: {:?}", reason)?;
+                        return Ok(Some(reason));
+                    }
+                    Err(err) => {
+                        writeln!(output, "error: {}", err)?;
+                        break;
+                    }
+                }
+            }
+            show_next(emu, &mut output)?;
+        } else if name == "c" || name == "continue" {
+            match emu.launch() {
+                Ok(StopReason::Breakpoint(address)) => {
+                    writeln!(output, "breakpoint at {:08X}", address)?;
+                    show_next(emu, &mut output)?;
+                }
+                Ok(reason) => {
+                    writeln!(output, "stopped: {:?}", reason)?;
+                    return Ok(Some(reason));
+                }
+                Err(err) => {
+                    writeln!(output, "error: {}", err)?;
+                    show_next(emu, &mut output)?;
+                }
+            }
+        } else if name == "b" || name == "break" || name == "d" || name == "delete" {
+            match args.first().and_then(|text| parse_number(text)) {
+                Some(address) if name.starts_with('b') => emu.add_breakpoint(address),
+                Some(address) => emu.remove_breakpoint(address),
+                None => writeln!(output, "usage: {} ADDR", name)?
+            }
+        } else if name == "i" || name == "info" {
+            for address in emu.breakpoints() {
+                writeln!(output, "breakpoint at {:08X}", address)?;
+            }
+        } else if name == "r" || name == "regs" {
+            show_registers(emu, &mut output)?;
+        } else if name == "set" {
+            set_register(emu, args, &mut output)?;
+        } else if name == "x" {
+            examine(emu, args, &mut output)?;
+        } else if name == "w" || name == "write" {
+            let values: Option<Vec<u32>> = args.iter().map(|text| parse_number(text)).collect();
+            match values {
+                Some(values) if values.len() >= 2 && values[1..].iter().all(|v| *v <= 0xff) => {
+                    let data: Vec<u8> = values[1..].iter().map(|v| *v as u8).collect();
+                    if let Err(err) = emu.load(values[0], &data) {
+                        writeln!(output, "error: {}", err)?;
+                    }
+                }
+                _ => writeln!(output, "usage: write ADDR BYTE...")?
+            }
+        } else if name == "u" || name == "disas" {
+            let address = match args.first() {
+                Some(text) => parse_number(text),
+                None => Some(emu.eip())
+            };
+            let count = match args.get(1) {
+                Some(text) => parse_number(text),
+                None => Some(8)
+            };
+            match (address, count) {
+                (Some(address), Some(count)) => disassemble(emu, address, count, &mut output)?,
+                _ => writeln!(output, "usage: disas [ADDR] [N]")?
+            }
+        } else if name == "h" || name == "help" {
+            writeln!(output, "{}", HELP)?;
+        } else {
+            writeln!(output, "unknown command: {} (try help)", name)?;
+        }
+    }
+}
+
+fn show_next<W: Write>(emu: &Emulator, output: &mut W) -> io::Result<()> {
+    return match emu.disassemble(emu.eip()) {
+        Some(insn) => writeln!(output, "{}", insn),
+        None => writeln!(output, "{:08X}: (bad)", emu.eip())
+    };
+}
+
+fn disassemble<W: Write>(emu: &Emulator, mut address: u32, count: u32, output: &mut W) -> io::Result<()> {
+    for _ in 0..count {
+        match emu.disassemble(address) {
+            Some(insn) => {
+                writeln!(output, "{}", insn)?;
+                address = address.wrapping_add(insn.length());
+            }
+            None => return writeln!(output, "{:08X}: (bad)", address)
+        }
+    }
+    return Ok(());
+}
+
+fn show_registers<W: Write>(emu: &Emulator, output: &mut W) -> io::Result<()> {
+    for (name, reg) in REGISTERS.iter() {
+        let value = emu.get_register(*reg);
+        writeln!(output, "{} = {:#010X} {}", name, value, value)?;
+    }
+    writeln!(output, "EIP = {:#010X}", emu.eip())?;
+    writeln!(output, "EFLAGS = {:#010X} [{}{}{}{}{}{}{}]", emu.eflags(),
+             if emu.is_carry() { " CF" } else { "" },
+             if emu.is_parity() { " PF" } else { "" },
+             if emu.is_auxiliary_carry() { " AF" } else { "" },
+             if emu.is_zero() { " ZF" } else { "" },
+             if emu.is_sign_flag() { " SF" } else { "" },
+             if emu.is_direction() { " DF" } else { "" },
+             if emu.is_overflow() { " OF" } else { "" })?;
+    return Ok(());
+}
+
+fn set_register<W: Write>(emu: &mut Emulator, args: &[&str], output: &mut W) -> io::Result<()> {
+    let value = match args.get(1).and_then(|text| parse_number(text)) {
+        Some(value) if args.len() == 2 => value,
+        _ => return writeln!(output, "usage: set REG VALUE")
+    };
+    let name = args[0].to_uppercase();
+    if name == "EIP" {
+        emu.set_eip(value);
+    } else if name == "EFLAGS" {
+        emu.set_eflags(value);
+    } else if let Ok(reg) = name.parse() {
+        emu.set_register(reg, value);
+    } else {
+        return writeln!(output, "unknown register: {}", args[0]);
+    }
+    return Ok(());
+}
+
+fn examine<W: Write>(emu: &Emulator, args: &[&str], output: &mut W) -> io::Result<()> {
+    let address = match args.first().and_then(|text| parse_number(text)) {
+        Some(address) => address,
+        None => return writeln!(output, "usage: x ADDR [COUNT]")
+    };
+    let count = match args.get(1) {
+        Some(text) => match parse_number(text) {
+            Some(count) => count,
+            None => return writeln!(output, "usage: x ADDR [COUNT]")
+        },
+        None => 64
+    };
+    let data = match emu.memory_slice(address, count) {
+        Ok(data) => data,
+        Err(err) => return writeln!(output, "error: {}", err)
+    };
+    for (i, line) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = line.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(output, "{:08X}: {}", address.wrapping_add(16 * i as u32), bytes.join(" "))?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::emulator::Register::*;
+    use crate::emulator::{Emulator, StopReason};
+
+    const TEST_MEMSIZE: u32 = 1024;
+
+    // mov eax,1; add eax,2; add eax,3; ret
+    const CODE: [u8; 12] = [0xb8, 0x01, 0x00, 0x00, 0x00, 0x83, 0xc0, 0x02, 0x83, 0xc0, 0x03, 0xc3];
+
+    fn session(commands: &str) -> (Emulator, Option<StopReason>, String) {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &CODE).unwrap();
+        let mut output = Vec::new();
+        let reason = run(&mut emu, commands.as_bytes(), &mut output).unwrap();
+        return (emu, reason, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn step_and_registers() {
+        let (emu, reason, output) = session("s\n\nr\nq\n");
+        assert_eq!(reason, None);
+        assert_eq!(emu.eip(), 8);
+        assert_eq!(emu.get_register(EAX), 3);
+        assert!(output.contains("00000008: 83 C0 03"));
+        assert!(output.contains("EAX = 0x00000003 3"));
+    }
+
+    #[test]
+    fn breakpoint_and_continue() {
+        let (emu, reason, output) = session("b 0x8\nc\nset eax 0x10\nc\n");
+        assert_eq!(reason, Some(StopReason::Exit(0x13)));
+        assert_eq!(emu.get_register(EAX), 0x13);
+        assert!(output.contains("breakpoint at 00000008"));
+    }
+
+    #[test]
+    fn memory_commands() {
+        let (emu, _, output) = session("w 0x100 1 2 0xff\nx 0x100 4\nw 0x100 256\nx 2000\nu 5 2\nq\n");
+        assert_eq!(emu.memory_slice(0x100, 3), Ok(&[1, 2, 0xff][..]));
+        assert!(output.contains("00000100: 01 02 FF 00\n"));
+        assert!(output.contains("usage: write ADDR BYTE..."));
+        assert!(output.contains("error: memory access out of bounds"));
+        assert!(output.contains("00000005: 83 C0 02                add EAX, 0x2\n00000008:"));
+    }
+
+    #[test]
+    fn end_of_input() {
+        let (_, reason, output) = session("bogus\n");
+        assert_eq!(reason, None);
+        assert!(output.contains("unknown command: bogus"));
+    }
+}
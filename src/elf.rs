@@ -0,0 +1,270 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::emulator::{Emulator, EmulatorError};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+const PT_LOAD: u32 = 1;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    UnsupportedClass(u8),
+    UnsupportedEncoding(u8),
+    UnsupportedType(u16),
+    UnsupportedMachine(u16),
+    BadProgramHeaders,
+    NoLoadSegments,
+    SegmentOutOfFile { index: usize },
+    SegmentSizeMismatch { index: usize },
+    Load(EmulatorError),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::TooShort => write!(f, "file is too short for an ELF header"),
+            ElfError::BadMagic => write!(f, "not an ELF file"),
+            ElfError::UnsupportedClass(class) => write!(f, "not a 32-bit ELF file (class {})", class),
+            ElfError::UnsupportedEncoding(data) => write!(f, "not a little-endian ELF file (encoding {})", data),
+            ElfError::UnsupportedType(kind) => write!(f, "not an executable ELF file (type {})", kind),
+            ElfError::UnsupportedMachine(machine) => write!(f, "not an i386 ELF file (machine {})", machine),
+            ElfError::BadProgramHeaders => write!(f, "program header table is malformed"),
+            ElfError::NoLoadSegments => write!(f, "no loadable segments"),
+            ElfError::SegmentOutOfFile { index } => write!(f, "segment {} extends past the end of the file", index),
+            ElfError::SegmentSizeMismatch { index } => {
+                write!(f, "segment {} has a file size larger than its memory size", index)
+            }
+            ElfError::Load(err) => write!(f, "segment does not fit in memory: {}", err),
+        }
+    }
+}
+
+impl Error for ElfError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub offset: u32,
+    pub vaddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+    pub flags: u32
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfFile {
+    pub entry: u32,
+    pub segments: Vec<Segment>
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    return data[offset] as u16 | (data[offset + 1] as u16) << 8;
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    return u16_at(data, offset) as u32 | (u16_at(data, offset + 2) as u32) << 16;
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    return data.starts_with(&ELF_MAGIC);
+}
+
+impl ElfFile {
+    // Reads the ELF header and the PT_LOAD program headers.
+    pub fn parse(data: &[u8]) -> Result<Self, ElfError> {
+        if !is_elf(data) {
+            return Err(if data.len() < ELF_MAGIC.len() { ElfError::TooShort } else { ElfError::BadMagic });
+        }
+        if data.len() < EHDR_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if data[4] != ELFCLASS32 {
+            return Err(ElfError::UnsupportedClass(data[4]));
+        }
+        if data[5] != ELFDATA2LSB {
+            return Err(ElfError::UnsupportedEncoding(data[5]));
+        }
+        let kind = u16_at(data, 16);
+        if kind != ET_EXEC {
+            return Err(ElfError::UnsupportedType(kind));
+        }
+        let machine = u16_at(data, 18);
+        if machine != EM_386 {
+            return Err(ElfError::UnsupportedMachine(machine));
+        }
+
+        let entry = u32_at(data, 24);
+        let phoff = u32_at(data, 28) as usize;
+        let phentsize = u16_at(data, 42) as usize;
+        let phnum = u16_at(data, 44) as usize;
+        if phnum == 0 {
+            return Err(ElfError::NoLoadSegments);
+        }
+        if phentsize < PHDR_SIZE || phoff as u64 + (phentsize * phnum) as u64 > data.len() as u64 {
+            return Err(ElfError::BadProgramHeaders);
+        }
+
+        let mut segments = Vec::new();
+        for index in 0..phnum {
+            let header = phoff + index * phentsize;
+            if u32_at(data, header) != PT_LOAD {
+                continue;
+            }
+            let segment = Segment {
+                offset: u32_at(data, header + 4),
+                vaddr: u32_at(data, header + 8),
+                filesz: u32_at(data, header + 16),
+                memsz: u32_at(data, header + 20),
+                flags: u32_at(data, header + 24)
+            };
+            if segment.offset as u64 + segment.filesz as u64 > data.len() as u64 {
+                return Err(ElfError::SegmentOutOfFile { index });
+            }
+            if segment.filesz > segment.memsz {
+                return Err(ElfError::SegmentSizeMismatch { index });
+            }
+            segments.push(segment);
+        }
+        if segments.is_empty() {
+            return Err(ElfError::NoLoadSegments);
+        }
+        return Ok(Self { entry, segments });
+    }
+
+    // first address past the highest loaded segment
+    pub fn end(&self) -> u32 {
+        return self.segments.iter().map(|s| s.vaddr.saturating_add(s.memsz)).max().unwrap_or(0);
+    }
+
+    // Copies each segment to its p_vaddr and zero-fills the rest of p_memsz.
+    pub fn load_segments(&self, emu: &mut Emulator, data: &[u8]) -> Result<(), ElfError> {
+        for segment in self.segments.iter() {
+            let start = segment.offset as usize;
+            let end = start + segment.filesz as usize;
+            emu.load(segment.vaddr, &data[start..end]).map_err(ElfError::Load)?;
+            let bss = segment.memsz - segment.filesz;
+            let address = segment.vaddr.wrapping_add(segment.filesz);
+            emu.fill(address, bss, 0).map_err(ElfError::Load)?;
+        }
+        return Ok(());
+    }
+}
+
+// Loads an ELF32 executable, points EIP at its entry and starts the
+// program break after the last segment.
+pub fn load(emu: &mut Emulator, data: &[u8]) -> Result<ElfFile, ElfError> {
+    let elf = ElfFile::parse(data)?;
+    elf.load_segments(emu, data)?;
+    emu.set_eip(elf.entry);
+    emu.set_program_break(elf.end());
+    return Ok(elf);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, ElfError, ElfFile};
+    use crate::emulator::{Emulator, EmulatorError};
+
+    const TEST_MEMSIZE: u32 = 0x2000;
+
+    fn put16(data: &mut [u8], offset: usize, value: u16) {
+        data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // ELF header, one PT_LOAD header at 52 and the code at 84
+    fn build(code: &[u8], vaddr: u32, memsz: u32) -> Vec<u8> {
+        let mut data = vec![0; 84];
+        data[..4].copy_from_slice(&[0x7f, b'E', b'L', b'F']);
+        data[4] = 1;
+        data[5] = 1;
+        data[6] = 1;
+        put16(&mut data, 16, 2);
+        put16(&mut data, 18, 3);
+        put32(&mut data, 20, 1);
+        put32(&mut data, 24, vaddr);
+        put32(&mut data, 28, 52);
+        put16(&mut data, 40, 52);
+        put16(&mut data, 42, 32);
+        put16(&mut data, 44, 1);
+        put32(&mut data, 52, 1);
+        put32(&mut data, 56, 84);
+        put32(&mut data, 60, vaddr);
+        put32(&mut data, 64, vaddr);
+        put32(&mut data, 68, code.len() as u32);
+        put32(&mut data, 72, memsz);
+        put32(&mut data, 76, 5);
+        data.extend_from_slice(code);
+        return data;
+    }
+
+    #[test]
+    fn load_segment() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0x1000, &[0xff; 0x20]).unwrap();
+        let data = build(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0xc3], 0x1000, 0x10);
+        let elf = load(&mut emu, &data).unwrap();
+        assert_eq!(elf.entry, 0x1000);
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.end(), 0x1010);
+        assert_eq!(emu.eip(), 0x1000);
+        assert_eq!(emu.program_break(), 0x2000);
+        assert_eq!(emu.memory_slice(0x1000, 6), Ok(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0xc3][..]));
+        // .bss is zero-filled up to p_memsz and no further
+        assert_eq!(emu.memory_slice(0x1006, 10), Ok(&[0; 10][..]));
+        assert_eq!(emu.memory_u8(0x1010), Ok(0xff));
+    }
+
+    #[test]
+    fn reject_malformed() {
+        let data = build(&[0xc3], 0x1000, 1);
+        assert_eq!(ElfFile::parse(&data[..3]), Err(ElfError::TooShort));
+        assert_eq!(ElfFile::parse(&data[..40]), Err(ElfError::TooShort));
+        assert_eq!(ElfFile::parse(&[0xc3; 64]), Err(ElfError::BadMagic));
+
+        let mut bad = data.clone();
+        bad[4] = 2;
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::UnsupportedClass(2)));
+        let mut bad = data.clone();
+        bad[5] = 2;
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::UnsupportedEncoding(2)));
+        let mut bad = data.clone();
+        bad[16] = 3;
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::UnsupportedType(3)));
+        let mut bad = data.clone();
+        bad[18] = 0x3e;
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::UnsupportedMachine(0x3e)));
+        let mut bad = data.clone();
+        bad[44] = 2;
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::BadProgramHeaders));
+        let mut bad = data.clone();
+        bad[52] = 4;
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::NoLoadSegments));
+        let mut bad = data.clone();
+        bad[68] = 2;
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::SegmentOutOfFile { index: 0 }));
+        let bad = build(&[0xc3, 0xc3], 0x1000, 1);
+        assert_eq!(ElfFile::parse(&bad), Err(ElfError::SegmentSizeMismatch { index: 0 }));
+    }
+
+    #[test]
+    fn reject_out_of_memory() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        let data = build(&[0xc3], 0x1ff0, 0x20);
+        match load(&mut emu, &data) {
+            Err(ElfError::Load(EmulatorError::MemoryOutOfBounds { .. })) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
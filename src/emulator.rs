@@ -1,35 +1,155 @@
+pub mod disasm;
+mod error;
+pub mod flags;
 mod modrm;
-use modrm::ModRM;
+mod syscall;
+mod trace;
+pub use disasm::Instruction;
+pub use error::EmulatorError;
+pub use trace::TraceLevel;
+use modrm::{Address, ModRM, RM};
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
 use Register::*;
 
-pub const DEBUG: bool = true;
 
 // 1MB 0x00000 - 0xfffff
 pub const MEMORY_SIZE: u32 = 1024 * 1024;
 
-enum Register {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
     EAX = 0, ECX = 1, EDX = 2, EBX = 3,
     ESP = 4, EBP = 5, ESI = 6, EDI = 7
 }
 
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_ascii_uppercase().as_str() {
+            "EAX" => Ok(EAX),
+            "ECX" => Ok(ECX),
+            "EDX" => Ok(EDX),
+            "EBX" => Ok(EBX),
+            "ESP" => Ok(ESP),
+            "EBP" => Ok(EBP),
+            "ESI" => Ok(ESI),
+            "EDI" => Ok(EDI),
+            _ => Err(format!("unknown register: {}", s))
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register8 {
+    AL = 0, CL = 1, DL = 2, BL = 3,
+    AH = 4, CH = 5, DH = 6, BH = 7
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register16 {
+    AX = 0, CX = 1, DX = 2, BX = 3,
+    SP = 4, BP = 5, SI = 6, DI = 7
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub address: u32,
+    pub length: u32,
+    pub kind: WatchKind
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Exit(u32),
+    Halt,
+    Breakpoint(u32),
+    Watchpoint(Watchpoint),
+    StepLimit
+}
+
 pub struct Emulator {
-    pub memory: Vec<u8>,
+    memory: Vec<u8>,
     eip: u32,
     register: [u32; 8],
-    eflags: u32
+    eflags: u32,
+    instruction_eip: u32,
+    instruction_count: u64,
+    breakpoints: Vec<u32>,
+    watchpoints: Vec<Watchpoint>,
+    // set by a guest memory access that hits a watchpoint
+    watch_hit: Cell<Option<Watchpoint>>,
+    step_limit: Option<u64>,
+    trace_level: TraceLevel,
+    operand_size: u32,
+    // F2 (REPNE) or F3 (REP/REPE) on the current instruction
+    rep_prefix: Option<u32>,
+    // a REP string instruction stopped between iterations
+    repeating: bool,
+    // `ret` to this address ends the program with EAX as its status
+    exit_address: Option<u32>,
+    // an OUT to this port ends the program with the value written
+    exit_port: Option<u16>,
+    process: syscall::Process
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(u32),
+    Memory(u32)
 }
 
-struct SIB {
-    scale: u32,
-    index: u32,
-    base: u32
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Operand::Register(index) => write!(f, "{}", register_name(*index)),
+            Operand::Memory(address) => write!(f, "[{:08X}]", address)
+        };
+    }
 }
 
 const REGISTER_NAME: [&str; 8] =
  ["EAX", "ECX", "EDX", "EBX", "ESP", "EBP", "ESI", "EDI"];
 
-fn register_name(index: u32) -> String {
-    return REGISTER_NAME[index as usize].to_string();
+const REGISTER_NAME8: [&str; 8] =
+ ["AL", "CL", "DL", "BL", "AH", "CH", "DH", "BH"];
+
+const REGISTER_NAME16: [&str; 8] =
+ ["AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI"];
+
+// condition codes, indexed by the low nibble of Jcc, SETcc and CMOVcc
+const CONDITION_NAME: [&str; 16] =
+ ["o", "no", "b", "ae", "z", "nz", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g"];
+
+// the eight ALU operations, indexed like the /n field of 80/81/83
+const ALU_NAME: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const ALU_ADD: u32 = 0;
+const ALU_OR: u32 = 1;
+const ALU_ADC: u32 = 2;
+const ALU_SBB: u32 = 3;
+const ALU_AND: u32 = 4;
+const ALU_SUB: u32 = 5;
+const ALU_XOR: u32 = 6;
+const ALU_CMP: u32 = 7;
+
+fn register_name(index: u32) -> &'static str {
+    return REGISTER_NAME[index as usize];
+}
+
+fn sized_register_name(index: u32, size: u32) -> &'static str {
+    return match size {
+        8 => REGISTER_NAME8[index as usize],
+        16 => REGISTER_NAME16[index as usize],
+        _ => REGISTER_NAME[index as usize]
+    };
 }
 
 impl Emulator {
@@ -38,926 +158,1600 @@ impl Emulator {
             memory: Vec::with_capacity(mem_size as usize),
             eip: 0,
             register: [0; 8],
-            eflags: 0
+            eflags: 0,
+            instruction_eip: 0,
+            instruction_count: 0,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            step_limit: None,
+            trace_level: TraceLevel::Off,
+            operand_size: 32,
+            rep_prefix: None,
+            repeating: false,
+            exit_address: Some(0),
+            exit_port: None,
+            process: syscall::Process::new(mem_size)
         };
 
         // initialize memory
         for _ in 0..mem_size {
             emu.memory.push(0);
         }
-        emu.register[ESP as usize] = mem_size - 4;
+        emu.register[ESP as usize] = mem_size.saturating_sub(4);
 
         return emu;
     }
 
-    fn esp(&self) -> u32 {
-        return self.register[ESP as usize];
+    pub fn eip(&self) -> u32 {
+        return self.eip;
     }
 
-    fn esp_add4(&mut self) {
-        self.register[ESP as usize] += 4;
+    pub fn set_eip(&mut self, value: u32) {
+        self.eip = value;
     }
 
-    fn esp_sub4(&mut self) {
-        self.register[ESP as usize] -= 4;
+    pub fn eflags(&self) -> u32 {
+        return self.eflags;
     }
 
-    fn epi_add4(&mut self) {
-        self.eip += 4;
+    pub fn set_eflags(&mut self, value: u32) {
+        self.eflags = value;
     }
 
-    fn epi_inc(&mut self) {
-        self.eip += 1;
+    pub fn get_register(&self, reg: Register) -> u32 {
+        return self.register[reg as usize];
     }
 
-    fn register(&self, index: u32) -> u32 {
-        return self.register[index as usize];
+    pub fn set_register(&mut self, reg: Register, value: u32) {
+        self.register[reg as usize] = value;
     }
 
-    fn memory(&self, address: u32) -> u8 {
-        return self.memory[(address) as usize];
+    pub fn get_register8(&self, reg: Register8) -> u8 {
+        return self.register_sized(reg as u32, 8) as u8;
     }
 
-    fn memory_i8(&self, address: u32) -> i8 {
-        return self.memory(address) as i8;
+    pub fn set_register8(&mut self, reg: Register8, value: u8) {
+        self.set_register_sized(reg as u32, 8, value as u32);
     }
 
-    fn push32(&mut self, value: u32) {
-        self.esp_sub4();
-        self.memory_set32(self.esp(), value);
+    pub fn get_register16(&self, reg: Register16) -> u16 {
+        return self.register_sized(reg as u32, 16) as u16;
     }
 
-    fn pop32(&mut self) -> u32 {
-        let value = self.memory_u32(self.esp());
-        self.esp_add4();
-        return value;
+    pub fn set_register16(&mut self, reg: Register16, value: u16) {
+        self.set_register_sized(reg as u32, 16, value as u32);
     }
 
-    fn memory_set8(&mut self, address: u32, value: u8) {
-        self.memory[address as usize] = value;
+    pub fn memory_size(&self) -> u32 {
+        return self.memory.len() as u32;
     }
 
-    fn memory_set32(&mut self, address: u32, value: u32) {
-        // println!("address: {:08X}", address);
-        // println!("value: {:08X}", value);
-        for i in 0..4 {
-            let mask = 0xff << 8*i;
-            let data = (value & mask) >> 8*i;
-            self.memory_set8(address + i, data as u8);
-            // println!("hex: {:02X}", temp);
+    pub fn instruction_count(&self) -> u64 {
+        return self.instruction_count;
+    }
+
+    pub fn add_breakpoint(&mut self, address: u32) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
         }
     }
 
-    fn memory_u32(&self, address: u32) -> u32 {
-        // println!("address: {:08X}", address);
-        let mut value: u32 = 0;
+    pub fn remove_breakpoint(&mut self, address: u32) {
+        self.breakpoints.retain(|&a| a != address);
+    }
 
-        for i in 0..4 {
-            let temp = self.memory(address + i) as u32;
-            // println!("hex: {:02X}", temp);
-            value += temp << 8 * i;
-        }
-        // println!("value: {:08X}", value);
+    pub fn breakpoints(&self) -> &[u32] {
+        return &self.breakpoints;
+    }
 
-        return value;
+    // true if `launch` would stop at EIP were it not the resume address
+    pub fn at_breakpoint(&self) -> bool {
+        return !self.repeating && self.breakpoints.contains(&self.eip);
     }
 
-    fn code8(&self, index: u32) -> u32 {
-        return self.memory(self.eip + index).into();
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
     }
 
-    fn sign_code8(&self, index: u32) -> i32 {
-        return self.memory_i8(self.eip + index).into();
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|w| *w != watchpoint);
     }
 
-    fn sign_code32(&self, index: u32) -> i32 {
-        let value = self.code32(index);
-        return value as i32;
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        return &self.watchpoints;
     }
 
-    fn code32(&self, index: u32) -> u32 {
-        let mut value: u32 = 0;
-        let mut data: String = String::new();
+    pub fn step_limit(&self) -> Option<u64> {
+        return self.step_limit;
+    }
 
-        for i in 0..4 {
-            let mut temp = self.code8(index + i);
-            let str = format!("{:02X} ", temp);
-            data.push_str(&str);
-            // println!("hex: {:2X}", temp);
-            // println!("bin: {:032b}", temp);
-            temp <<= 8 * i;
-            // println!("bin: {:032b}", temp);
-            value += temp;
-        }
-        if DEBUG {
-            println!("data: {}", data);
-        }
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
 
-        return value;
+    pub fn exit_address(&self) -> Option<u32> {
+        return self.exit_address;
     }
 
-    fn read_modrm(&mut self) -> ModRM {
-        let code = self.code8(0);
-        self.epi_inc();
-        return ModRM::new(code);
+    pub fn set_exit_address(&mut self, address: Option<u32>) {
+        self.exit_address = address;
     }
 
-    fn read_sib(&mut self) -> SIB {
-        let code = self.code8(0);
-        self.epi_inc();
-        if DEBUG {
-            println!("SIB: {:02X} {:#8b}", code, code);
-        }
+    pub fn set_exit_port(&mut self, port: Option<u16>) {
+        self.exit_port = port;
+    }
 
-        let mut sib = SIB {
-            scale: 0,
-            index: 0,
-            base: 0
+    // Stores the exit address at [ESP] as the return address of the entry
+    // point, so a plain `ret` from it ends the program.
+    pub fn store_exit_address(&mut self) -> Result<(), EmulatorError> {
+        return match self.exit_address {
+            Some(address) => self.memory_set32(self.get_register(ESP), address),
+            None => Ok(())
         };
+    }
 
-        let mod_mask = 0b11000000;
-        sib.scale = (code & mod_mask) >> 6;
+    pub fn trace_level(&self) -> TraceLevel {
+        return self.trace_level;
+    }
+
+    pub fn set_trace_level(&mut self, level: TraceLevel) {
+        self.trace_level = level;
+    }
 
-        let reg_mask = 0b00111000;
-        sib.index = (code & reg_mask) >> 3;
+    fn tracing(&self, level: TraceLevel) -> bool {
+        return self.trace_level >= level;
+    }
 
-        let rm_mask = 0b00000111;
-        sib.base = code & rm_mask;
+    pub fn load(&mut self, address: u32, data: &[u8]) -> Result<(), EmulatorError> {
+        let start = self.check_access(address, data.len() as u32)?;
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        return Ok(());
+    }
 
-        if DEBUG {
-            println!("scale: {:02b}, index: {:03b}, base {:03b}",
-                     sib.scale, sib.index, sib.base);
+    pub fn fill(&mut self, address: u32, size: u32, value: u8) -> Result<(), EmulatorError> {
+        let start = self.check_access(address, size)?;
+        for byte in self.memory[start..start + size as usize].iter_mut() {
+            *byte = value;
         }
-        return sib;
+        return Ok(());
     }
 
-    fn read_effective_address(&mut self) -> (u32, u32) {
-        let modrm = self.read_modrm();
-        return self.read_effective_address_from_modrm(&modrm);
+    pub fn memory_slice(&self, address: u32, size: u32) -> Result<&[u8], EmulatorError> {
+        let start = self.check_access(address, size)?;
+        return Ok(&self.memory[start..start + size as usize]);
     }
 
-    fn read_effective_address_from_modrm(&mut self, modrm: &ModRM) -> (u32, u32) {
-        if modrm.mode == 0b01 {
-            if modrm.rm == 0b100 {
-                let sib = self.read_sib();
-                let disp = self.sign_code8(0);
-                self.epi_inc();
-                if sib.scale == 0 && sib.index == 0b100 {
-                    if DEBUG {
-                        let reg_name = register_name(sib.base);
-                        println!("address: [{} {}]", reg_name, disp);
-                    }
-                    let temp = self.register(sib.base) as i32;
-                    let address = (temp + disp) as u32;
-                    return (modrm.reg, address);
-                } else {
-                    unimplemented!("not implemented ModR/M rm: 100");
-                }
-            } else {
-                let disp = self.sign_code8(0);
-                self.epi_inc();
-                if DEBUG {
-                    let reg_name = register_name(modrm.rm);
-                    println!("address: [{} {}]", reg_name, disp);
-                }
-                let temp = self.register(modrm.rm) as i32;
-                let address = (temp + disp) as u32;
-                return (modrm.reg, address);
-            }
-        } else if modrm.mode == 0b10 {
-            if modrm.rm == 0b100 {
-                let sib = self.read_sib();
-                let disp = self.sign_code32(0);
-                self.epi_add4();
-                if sib.scale == 0 && sib.index == 0b100 {
-                    if DEBUG {
-                        let reg_name2 = register_name(sib.base);
-                        println!("address: [{} {}]", reg_name2, disp);
-                    }
-                    let temp = self.register(sib.base) as i32;
-                    let address = (temp + disp) as u32;
-                    return (modrm.reg, address);
-                } else {
-                    unimplemented!("");
-                }
-            } else {
-                unimplemented!("");
-            }
-        } else {
-            unimplemented!("unknown Mod: {:02b}", modrm.mode);
+    // decodes the instruction at address without executing it
+    pub fn disassemble(&self, address: u32) -> Option<Instruction> {
+        let start = address as usize;
+        if start >= self.memory.len() {
+            return None;
         }
+        let end = (start + disasm::MAX_LENGTH).min(self.memory.len());
+        return disasm::disassemble(&self.memory[start..end], address);
     }
 
-    fn leave(&mut self) {
-        if DEBUG {
-            println!("leave");
+    fn instruction_bytes(&self) -> Vec<u8> {
+        let start = self.instruction_eip as usize;
+        let end = (self.eip as usize).min(self.memory.len());
+        if start >= end {
+            return Vec::new();
         }
-        self.register[ESP as usize] = self.register[EBP as usize];
-        self.register[EBP as usize] = self.pop32();
+        return self.memory[start..end].to_vec();
     }
 
-    fn jump(&mut self, value: i32) {
-        let mut address = self.eip as i32;
-        address += value;
-        if DEBUG {
-            println!("jump => {:08X}", address);
-        }
-        self.eip = address as u32;
+    fn unknown_opcode(&self) -> EmulatorError {
+        return EmulatorError::UnknownOpcode {
+            eip: self.instruction_eip,
+            bytes: self.instruction_bytes()
+        };
     }
 
-    fn jump_short(&mut self) {
-        let value = self.sign_code8(0);
-        if DEBUG {
-            println!("jmp short, {:08X}, {}", value, value);
-        }
-        self.jump(value + 1);
+    fn divide_error(&self) -> EmulatorError {
+        return EmulatorError::DivideError {
+            eip: self.instruction_eip,
+            bytes: self.instruction_bytes()
+        };
     }
 
-    fn shr_rm32(&mut self, modrm: ModRM) {
-        if modrm.mode == 0b11 {
-            if DEBUG {
-                let reg_name = register_name(modrm.reg);
-                println!("shr: {},{}", reg_name, 1);
-            }
-            self.register[modrm.reg as usize] = self.register[modrm.reg as usize] >> 1;
-        } else {
-            unimplemented!();
-        }
+    fn unsupported_addressing(&self) -> EmulatorError {
+        return EmulatorError::UnsupportedAddressing {
+            eip: self.instruction_eip,
+            bytes: self.instruction_bytes()
+        };
     }
 
-    fn push_rm32(&mut self, modrm: ModRM) {
-        if modrm.mode == 0b01 {
-            let (_reg, address) = self.read_effective_address_from_modrm(&modrm);
-            if DEBUG {
-                println!("push: [{:08X}]", address);
-            }
-            let value = self.memory_u32(address);
-            if DEBUG {
-                println!("push: {:08X}", value);
+    fn watch(&self, address: u32, size: u32, write: bool) {
+        if self.watch_hit.get().is_some() {
+            return;
+        }
+        for watchpoint in self.watchpoints.iter() {
+            let kind = match watchpoint.kind {
+                WatchKind::Write => write,
+                WatchKind::Read => !write,
+                WatchKind::Access => true
+            };
+            let overlap = (address as u64) < watchpoint.address as u64 + watchpoint.length as u64
+                && (watchpoint.address as u64) < address as u64 + size as u64;
+            if kind && overlap {
+                self.watch_hit.set(Some(*watchpoint));
+                return;
             }
-            self.push32(value);
-        } else {
-            unimplemented!("unknown Mod");
         }
     }
 
-    fn push_r32(&mut self, code: u32) {
-        let reg = code - 0x50;
-        let reg_name = register_name(reg);
-        if DEBUG {
-            println!("push {}", reg_name);
+    fn check_access(&self, address: u32, size: u32) -> Result<usize, EmulatorError> {
+        let end = address as u64 + size as u64;
+        if end > self.memory.len() as u64 {
+            return Err(EmulatorError::MemoryOutOfBounds {
+                eip: self.instruction_eip,
+                bytes: self.instruction_bytes(),
+                address,
+                size
+            });
         }
-        self.esp_sub4();
-        self.memory_set32(self.esp(), self.register(reg));
+        return Ok(address as usize);
     }
 
-    fn push_imm8(&mut self) {
-        let value = self.code8(0);
-        self.epi_inc();
-        if DEBUG {
-            println!("push {:#04X} {}", value, value);
-        }
-        self.push32(value as u32);
+    fn esp(&self) -> u32 {
+        return self.register[ESP as usize];
     }
 
-    fn pop_r32(&mut self, code: u32) {
-        let reg = code - 0x58;
-        let reg_name = register_name(reg);
-        if DEBUG {
-            println!("pop {}", reg_name);
-        }
-        let value = self.pop32();
-        if DEBUG {
-            println!("value: {:X}", value);
-        }
-        self.register[reg as usize] = value;
+    // ESP and EIP wrap around like on the real CPU; the next memory
+    // access through them then faults instead of the host panicking
+    fn esp_add4(&mut self) {
+        self.register[ESP as usize] = self.esp().wrapping_add(4);
     }
 
-    fn add_rm32_imm32(&mut self, modrm: ModRM) {
-        let reg_name = register_name(modrm.rm);
-        let value = self.code32(0);
-        if DEBUG {
-            println!("add {},{}", reg_name, value);
-        }
-        self.epi_add4();
-        self.register[modrm.rm as usize] += value;
+    fn epi_add4(&mut self) {
+        self.eip = self.eip.wrapping_add(4);
+    }
+
+    fn epi_inc(&mut self) {
+        self.eip = self.eip.wrapping_add(1);
+    }
+
+    fn register(&self, index: u32) -> u32 {
+        return self.register[index as usize];
+    }
+
+    // 8-bit registers 0-3 are the low bytes of EAX-EBX, 4-7 the high bytes
+    fn register_sized(&self, index: u32, size: u32) -> u32 {
+        return match size {
+            8 if index < 4 => self.register[index as usize] & 0xff,
+            8 => (self.register[index as usize - 4] >> 8) & 0xff,
+            16 => self.register[index as usize] & 0xffff,
+            _ => self.register[index as usize]
+        };
     }
 
-    fn add_rm32_imm8(&mut self, modrm: ModRM) {
-        if modrm.mode == 0b01 {
-            let (_reg, address) = self.read_effective_address_from_modrm(&modrm);
-            let value = self.sign_code8(0);
-            if DEBUG {
-                println!("add [{:08X}],{}", address, value);
+    fn set_register_sized(&mut self, index: u32, size: u32, value: u32) {
+        match size {
+            8 if index < 4 => {
+                let reg = &mut self.register[index as usize];
+                *reg = (*reg & !0xff) | (value & 0xff);
             }
-            self.epi_inc();
-            let temp = self.memory_u32(address) as i32;
-            self.memory_set32(address, (temp + value) as u32);
-        } else if modrm.mode == 0b11 {
-            let reg_name = register_name(modrm.rm);
-            let value = self.sign_code8(0);
-            if DEBUG {
-                println!("add {},{}", reg_name, value);
+            8 => {
+                let reg = &mut self.register[index as usize - 4];
+                *reg = (*reg & !0xff00) | ((value & 0xff) << 8);
             }
-            self.epi_inc();
-            let temp = self.register(modrm.rm) as i32;
-            self.register[modrm.rm as usize] = (temp + value) as u32;
-        } else {
-            unimplemented!();
+            16 => {
+                let reg = &mut self.register[index as usize];
+                *reg = (*reg & !0xffff) | (value & 0xffff);
+            }
+            _ => self.register[index as usize] = value
         }
     }
 
-    fn sub_rm32_imm32(&mut self, modrm: ModRM) {
-        let reg_name = register_name(modrm.rm);
-        let value = self.code32(0);
-        if DEBUG {
-            println!("sub {},{}", reg_name, value);
+    pub fn memory_u8(&self, address: u32) -> Result<u8, EmulatorError> {
+        let index = self.check_access(address, 1)?;
+        self.watch(address, 1, false);
+        let value = self.memory[index];
+        if self.tracing(TraceLevel::Memory) {
+            eprintln!("read8 [{:08X}] => {:02X}", address, value);
         }
-        self.epi_add4();
-        self.register[modrm.rm as usize] -= value;
+        return Ok(value);
     }
 
-    fn sub_rm32_imm8(&mut self, modrm: ModRM) {
-        if modrm.mode == 0b01 {
-            let (_reg, address) = self.read_effective_address_from_modrm(&modrm);
-            let value = self.sign_code8(0);
-            if DEBUG {
-                println!("sub [{:08X}],{}", address, value);
-            }
-            self.epi_inc();
-            let temp = self.memory_u32(address) as i32;
-            self.memory_set32(address, (temp - value) as u32);
-        } else if modrm.mode == 0b11 {
-            let reg_name = register_name(modrm.rm);
-            let value = self.sign_code8(0);
-            if DEBUG {
-                println!("sub {},{}", reg_name, value);
-            }
-            self.epi_inc();
-            let temp = self.register(modrm.rm) as i32;
-            self.register[modrm.rm as usize] = (temp - value) as u32;
+    pub fn push32(&mut self, value: u32) -> Result<(), EmulatorError> {
+        return self.push_sized(32, value);
+    }
+
+    pub fn pop32(&mut self) -> Result<u32, EmulatorError> {
+        let value = self.memory_u32(self.esp())?;
+        self.esp_add4();
+        return Ok(value);
+    }
+
+    pub fn memory_set8(&mut self, address: u32, value: u8) -> Result<(), EmulatorError> {
+        let index = self.check_access(address, 1)?;
+        self.watch(address, 1, true);
+        if self.tracing(TraceLevel::Memory) {
+            eprintln!("write8 [{:08X}] <= {:02X}", address, value);
         }
+        self.memory[index] = value;
+        return Ok(());
     }
 
-    fn opcode0f(&mut self) {
-        let code = self.code8(0);
-        self.epi_inc();
-        if DEBUG {
-            println!("opcode: {:02X}", code);
+    pub fn memory_set32(&mut self, address: u32, value: u32) -> Result<(), EmulatorError> {
+        let index = self.check_access(address, 4)?;
+        self.watch(address, 4, true);
+        if self.tracing(TraceLevel::Memory) {
+            eprintln!("write32 [{:08X}] <= {:08X}", address, value);
         }
-        if code == 0x84 {
-            self.jz_rel32();
-        } else if code == 0x85 {
-            self.jnz_rel32();
-        } else {
-            unimplemented!();
+        for i in 0..4 {
+            let mask = 0xff << (8 * i);
+            let data = (value & mask) >> (8 * i);
+            self.memory[index + i] = data as u8;
         }
+        return Ok(());
     }
 
-    fn opcode81(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.opcode == 0 {
-            self.add_rm32_imm32(modrm);
-        } else if modrm.opcode == 5 {
-            self.sub_rm32_imm32(modrm);
-        } else {
-            unimplemented!("unknown opcode: {}", modrm.opcode);
+    pub fn memory_u16(&self, address: u32) -> Result<u16, EmulatorError> {
+        let index = self.check_access(address, 2)?;
+        self.watch(address, 2, false);
+        let value = self.memory[index] as u16 | (self.memory[index + 1] as u16) << 8;
+        if self.tracing(TraceLevel::Memory) {
+            eprintln!("read16 [{:08X}] => {:04X}", address, value);
         }
+        return Ok(value);
     }
 
-    fn opcode83(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.opcode == 0 {
-            self.add_rm32_imm8(modrm);
-        } else if modrm.opcode == 4 {
-            self.and_rm32_imm8(modrm)
-        } else if modrm.opcode == 5 {
-            self.sub_rm32_imm8(modrm);
-        } else if modrm.opcode == 7 {
-            self.cmp_rm32_imm8(modrm);
-        } else {
-            unimplemented!("unknown sub opcode: {}", modrm.opcode);
+    pub fn memory_set16(&mut self, address: u32, value: u16) -> Result<(), EmulatorError> {
+        let index = self.check_access(address, 2)?;
+        self.watch(address, 2, true);
+        if self.tracing(TraceLevel::Memory) {
+            eprintln!("write16 [{:08X}] <= {:04X}", address, value);
         }
+        self.memory[index] = value as u8;
+        self.memory[index + 1] = (value >> 8) as u8;
+        return Ok(());
     }
 
-    fn opcodeff(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.opcode == 6 {
-            self.push_rm32(modrm);
-        } else {
-            unimplemented!("unknown sub opcode: {}", modrm.opcode);
-        }
+    fn memory_sized(&self, address: u32, size: u32) -> Result<u32, EmulatorError> {
+        return match size {
+            8 => Ok(self.memory_u8(address)? as u32),
+            16 => Ok(self.memory_u16(address)? as u32),
+            _ => self.memory_u32(address)
+        };
     }
 
-    fn opcoded1(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.opcode == 5 {
-            self.shr_rm32(modrm);
-        } else {
-            unimplemented!("unknown sub opcode: {}", modrm.opcode);
-        }
+    fn set_memory_sized(&mut self, address: u32, size: u32, value: u32) -> Result<(), EmulatorError> {
+        return match size {
+            8 => self.memory_set8(address, value as u8),
+            16 => self.memory_set16(address, value as u16),
+            _ => self.memory_set32(address, value)
+        };
     }
 
+    pub fn memory_u32(&self, address: u32) -> Result<u32, EmulatorError> {
+        let index = self.check_access(address, 4)?;
+        self.watch(address, 4, false);
+        let mut value: u32 = 0;
+
+        for i in 0..4 {
+            let temp = self.memory[index + i] as u32;
+            value += temp << (8 * i);
+        }
+        if self.tracing(TraceLevel::Memory) {
+            eprintln!("read32 [{:08X}] => {:08X}", address, value);
+        }
 
-    fn is_carry(&self) -> bool {
-        return (self.eflags & 1) == 1;
+        return Ok(value);
     }
 
-    fn is_zero(&self) -> bool {
-        return (self.eflags & 1 << 6) == 1 << 6;
+    fn code8(&self, index: u32) -> Result<u32, EmulatorError> {
+        let address = self.check_access(self.eip.wrapping_add(index), 1)?;
+        return Ok(self.memory[address].into());
     }
 
-    fn is_sign_flag(&self) -> bool {
-        return (self.eflags & 1 << 7) == 1 << 7;
+    fn sign_code8(&self, index: u32) -> Result<i32, EmulatorError> {
+        let value = self.code8(index)? as u8;
+        return Ok((value as i8).into());
     }
 
-    fn is_overflow(&self) -> bool {
-        return (self.eflags & 1 << 11) == 1 << 11;
+    fn sign_code32(&self, index: u32) -> Result<i32, EmulatorError> {
+        let value = self.code32(index)?;
+        return Ok(value as i32);
     }
 
-    fn jz_rel8(&mut self) {
-        let value = self.sign_code8(0);
-        self.epi_inc();
-        if DEBUG {
-            println!("jz {:08X}", value);
-            println!("eflags = {:032b}", self.eflags);
-        }
-        if self.is_zero() {
-            self.jump(value);
-        };
+    fn code16(&self, index: u32) -> Result<u32, EmulatorError> {
+        return Ok(self.code8(index)? | self.code8(index + 1)? << 8);
     }
 
-    fn jz_rel32(&mut self) {
-        let value = self.sign_code32(0);
-        self.epi_add4();
-        if DEBUG {
-            println!("jz {:08X}", value);
-            println!("eflags = {:032b}", self.eflags);
-        }
-        if self.is_zero() {
-            self.jump(value);
+    // reads an immediate of the given size and moves EIP past it
+    fn read_imm(&mut self, size: u32) -> Result<u32, EmulatorError> {
+        let value = match size {
+            8 => self.code8(0)?,
+            16 => self.code16(0)?,
+            _ => self.code32(0)?
         };
+        self.eip = self.eip.wrapping_add(size / 8);
+        return Ok(value);
     }
 
-    fn jnz_rel8(&mut self) {
-        let value = self.sign_code8(0);
+    // reads a sign-extended imm8 for an operand of the given size
+    fn read_simm8(&mut self, size: u32) -> Result<u32, EmulatorError> {
+        let value = self.sign_code8(0)? as u32;
         self.epi_inc();
-        if DEBUG {
-            println!("jnz {:08X}", value);
-            println!("eflags = {:032b}", self.eflags);
-        }
-        if !self.is_zero() {
-            self.jump(value);
-        };
+        return Ok(value & flags::mask(size));
     }
 
-    fn jnz_rel32(&mut self) {
-        let value = self.sign_code32(0);
-        self.epi_add4();
-        if DEBUG {
-            println!("jnz {:08X}", value);
-            println!("eflags = {:032b}", self.eflags);
+    fn code32(&self, index: u32) -> Result<u32, EmulatorError> {
+        let mut value: u32 = 0;
+
+        for i in 0..4 {
+            let temp = self.code8(index + i)?;
+            value += temp << (8 * i);
         }
-        if !self.is_zero() {
-            self.jump(value);
-        };
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("data: {:02X} {:02X} {:02X} {:02X}",
+                      value & 0xff, (value >> 8) & 0xff, (value >> 16) & 0xff, value >> 24);
+        }
+
+        return Ok(value);
     }
 
-    fn jg_rel8(&mut self) {
-        let value = self.sign_code8(0);
+    fn read_modrm(&mut self) -> Result<ModRM, EmulatorError> {
+        let code = self.code8(0)?;
         self.epi_inc();
-        if DEBUG {
-            println!("jg {:08X}", value);
-            println!("eflags = {:032b}", self.eflags);
+        let modrm = ModRM::new(code);
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("ModR/M: {:02X} {:#8b}", code, code);
+            eprintln!("Mod: {:02b}, REG: {:03b} (opcode: {}, {}), R/M: {:03b} ({})",
+                      modrm.mode, modrm.reg, modrm.opcode, register_name(modrm.reg),
+                      modrm.rm, register_name(modrm.rm));
         }
-        if !self.is_zero() && (self.is_sign_flag() == self.is_overflow()) {
-            self.jump(value);
-        };
+        return Ok(modrm);
     }
 
-    fn jng_rel8(&mut self) {
-        let value = self.sign_code8(0);
-        self.epi_inc();
-        if DEBUG {
-            println!("jng {:08X}", value);
-            println!("eflags = {:032b}", self.eflags);
-        }
-        if self.is_zero() || (self.is_sign_flag() != self.is_overflow()) {
-            self.jump(value);
-        };
+    fn read_effective_address(&mut self) -> Result<(u32, Operand), EmulatorError> {
+        let modrm = self.read_modrm()?;
+        let operand = self.read_effective_address_from_modrm(&modrm)?;
+        return Ok((modrm.reg, operand));
     }
 
-    fn cmp_base(&mut self, target: u32, value: u32, sign_value: i32) {
-        let sign_target = target as i32;
-        let (result, carry_flag) = target.overflowing_sub(value);
-        if DEBUG {
-            println!("result {}, {:08X}", result, result);
-        }
-        // CF: Carry Flag
-        if carry_flag {
-            if DEBUG {
-                println!("carry flag");
+    fn read_effective_address_from_modrm(&mut self, modrm: &ModRM) -> Result<Operand, EmulatorError> {
+        let rm = modrm.decode(|| {
+            let code = self.code8(0)?;
+            self.epi_inc();
+            return Ok(code as u8);
+        })?;
+        let operand = match rm {
+            RM::Register(index) => Operand::Register(index),
+            RM::Memory(address) => Operand::Memory(self.effective_address(&address))
+        };
+        if self.tracing(TraceLevel::Decode) {
+            if let RM::Memory(address) = rm {
+                eprintln!("address: {} => {}", address, operand);
             }
-            self.eflags |= 1;
-        } else {
-            self.eflags &= !1;
         }
-        // ZF: Zero Flag
-        if result == 0 {
-            if DEBUG {
-                println!("zero flag");
-            }
-            self.eflags |= 1 << 6;
-        } else {
-            self.eflags &= !(1 << 6);
+        return Ok(operand);
+    }
+
+    fn effective_address(&self, address: &Address) -> u32 {
+        let mut value = address.disp as u32;
+        if let Some(base) = address.base {
+            value = value.wrapping_add(self.register(base));
         }
-        // SF: Sign Flag
-        if (result >> 31) == 1 {
-            if DEBUG {
-                println!("sign flag");
-            }
-            self.eflags |= 1 << 7;
-        } else {
-            self.eflags &= !(1 << 7);
+        if let Some(index) = address.index {
+            value = value.wrapping_add(self.register(index).wrapping_mul(address.scale));
         }
-        // OF: Overflow Flag
-        if sign_target.checked_sub(sign_value) == None {
-            if DEBUG {
-                println!("overflow flag");
-            }
-            self.eflags |= 1 << 11;
-        } else {
-            self.eflags &= !(1 << 11);
+        return value;
+    }
+
+    fn rm(&self, operand: Operand, size: u32) -> Result<u32, EmulatorError> {
+        return match operand {
+            Operand::Register(index) => Ok(self.register_sized(index, size)),
+            Operand::Memory(address) => self.memory_sized(address, size)
+        };
+    }
+
+    fn set_rm(&mut self, operand: Operand, size: u32, value: u32) -> Result<(), EmulatorError> {
+        match operand {
+            Operand::Register(index) => self.set_register_sized(index, size, value),
+            Operand::Memory(address) => self.set_memory_sized(address, size, value)?
         }
-        if DEBUG {
-            println!("eflags = {:032b}", self.eflags);
+        return Ok(());
+    }
+
+    fn leave(&mut self) -> Result<(), EmulatorError> {
+        self.register[ESP as usize] = self.register[EBP as usize];
+        self.register[EBP as usize] = self.pop32()?;
+        return Ok(());
+    }
+
+    fn jump(&mut self, value: i32) {
+        let address = self.eip.wrapping_add(value as u32);
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("jump => {:08X}", address);
         }
+        self.eip = address;
     }
 
-    fn cmp_u32_u32(&mut self, target: u32, value: u32) {
-        self.cmp_base(target, value, value as i32);
+    fn jump_short(&mut self) -> Result<(), EmulatorError> {
+        let value = self.sign_code8(0)?;
+        self.jump(value + 1);
+        return Ok(());
     }
 
-    fn cmp_u32_i32(&mut self, target: u32, sign_value: i32) {
-        self.cmp_base(target, sign_value as u32, sign_value);
+    // E9
+    fn jump_near(&mut self) -> Result<(), EmulatorError> {
+        let value = self.sign_code32(0)?;
+        self.epi_add4();
+        self.jump(value);
+        return Ok(());
     }
 
-    fn cmp_r32_rm32(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.mode == 0b01 {
-            let (reg, address) = self.read_effective_address_from_modrm(&modrm);
-            let reg_name = register_name(reg);
-            if DEBUG {
-                println!("cmp {},[{:08X}]", reg_name, address);
-            }
-            let target = self.register[reg as usize];
-            let value = self.memory_u32(address);
-            if DEBUG {
-                println!("cmp {},{}", reg_name, value);
-                println!("value: {}", value);
-            }
-            self.cmp_u32_u32(target, value);
+    // E0 LOOPNE, E1 LOOPE, E2 LOOP and E3 JECXZ; none of them touch the flags
+    fn loop_rel8(&mut self, code: u32) -> Result<(), EmulatorError> {
+        let value = self.sign_code8(0)?;
+        self.epi_inc();
+        let taken = if code == 0xe3 {
+            self.register(ECX as u32) == 0
         } else {
-            unimplemented!("unknown Mod: {:02b}", modrm.mode);
+            let count = self.register(ECX as u32).wrapping_sub(1);
+            self.set_register(ECX, count);
+            count != 0 && (code == 0xe2 || self.is_zero() == (code == 0xe1))
+        };
+        if taken {
+            self.jump(value);
         }
+        return Ok(());
     }
 
-    fn cmp_rm32_imm8(&mut self, modrm: ModRM) {
-        let target: u32;
-        if modrm.mode == 0b01 || modrm.mode == 0b10 {
-            let (_reg, address) = self.read_effective_address_from_modrm(&modrm);
-            if DEBUG {
-                print!("cmp [{:08X}],", address);
-            }
-            target = self.memory_u32(address) as u32;
-        } else if modrm.mode == 0b11 {
-            let reg_name = register_name(modrm.rm);
-            if DEBUG {
-                print!("cmp {},", reg_name);
-            }
-            target = self.register[modrm.rm as usize] as u32;
-        } else {
-            unimplemented!("unknown Mod");
-        }
-        let sign_value = self.sign_code8(0) as i32;
-        self.epi_inc();
-        if DEBUG {
-            println!("value: {}", sign_value);
-        }
-        self.cmp_u32_i32(target, sign_value);
+    // pushes a word or dword as selected by the operand size
+    fn push_sized(&mut self, size: u32, value: u32) -> Result<(), EmulatorError> {
+        let esp = self.esp().wrapping_sub(size / 8);
+        self.set_memory_sized(esp, size, value)?;
+        self.register[ESP as usize] = esp;
+        return Ok(());
     }
 
-    fn nop(&mut self) {
-        if DEBUG {
-            println!("nop");
-        }
+    fn pop_sized(&mut self, size: u32) -> Result<u32, EmulatorError> {
+        let value = self.memory_sized(self.esp(), size)?;
+        self.register[ESP as usize] = self.esp().wrapping_add(size / 8);
+        return Ok(value);
     }
 
-    fn lea(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.mode == 0b01 ||  modrm.mode == 0b10 {
-            let (reg, address) = self.read_effective_address_from_modrm(&modrm);
-            let reg_name = register_name(reg);
-            if DEBUG {
-                println!("lea {},[{:08X}]", reg_name, address);
-            }
-            self.register[reg as usize] = address;
-        } else {
-            unimplemented!("unknown Mod");
+    fn push_rm(&mut self, modrm: ModRM) -> Result<(), EmulatorError> {
+        let operand = self.read_effective_address_from_modrm(&modrm)?;
+        let value = self.rm(operand, self.operand_size)?;
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("value: {:08X}", value);
         }
+        self.push_sized(self.operand_size, value)?;
+        return Ok(());
     }
 
-    fn xor_rm32_r32(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.mode == 0b11 {
-            if DEBUG {
-                let reg_name1 = register_name(modrm.rm);
-                let reg_name2 = register_name(modrm.reg);
-                println!("xor {},{}", reg_name1, reg_name2);
-            }
-            self.register[modrm.rm as usize] ^= self.register[modrm.reg as usize];
-        } else {
-            unimplemented!("unknown Mod");
+    // 8F /0: the address is computed after ESP has been incremented
+    fn pop_rm(&mut self) -> Result<(), EmulatorError> {
+        let esp = self.esp();
+        let result = self.pop_to_rm(self.operand_size);
+        if result.is_err() {
+            self.register[ESP as usize] = esp;
         }
+        return result;
     }
 
-    fn and_rm32_imm8(&mut self, modrm: ModRM) {
-        if modrm.mode == 0b11 {
-            let reg_name = register_name(modrm.rm);
-            let value = self.sign_code8(0);
-            if DEBUG {
-                println!("and {},{}", reg_name, value);
-            }
-            self.epi_inc();
-            let temp = self.register(modrm.rm) as i32;
-            self.register[modrm.rm as usize] = (temp & value) as u32;
-        } else {
-            unimplemented!("unknown Mod");
+    fn pop_to_rm(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let value = self.pop_sized(size)?;
+        let (opcode, operand) = self.read_effective_address()?;
+        if opcode != 0 {
+            return Err(self.unknown_opcode());
         }
+        self.set_rm(operand, size, value)?;
+        return Ok(());
     }
 
-    fn add_eax_imm32(&mut self) {
-        let value = self.code32(0);
-        if DEBUG {
-            println!("add EAX,{:08X}", value);
-        }
-        self.register[EAX as usize] += value;
-        self.epi_add4();
+    fn push_r(&mut self, code: u32) -> Result<(), EmulatorError> {
+        let reg = code - 0x50;
+        self.push_sized(self.operand_size, self.register_sized(reg, self.operand_size))?;
+        return Ok(());
     }
 
-    fn add_r32_rm32(&mut self) {
-        let (reg, address) = self.read_effective_address();
-        self.register[reg as usize] += self.memory_u32(address);
+    // 68 and 6A; the imm8 form is sign-extended
+    fn push_imm(&mut self, imm_size: u32) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let value = if imm_size == 8 { self.read_simm8(size)? } else { self.read_imm(size)? };
+        self.push_sized(size, value)?;
+        return Ok(());
     }
 
-    fn add_rm32_r32(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.mode == 0b01 {
-            let (reg, address) = self.read_effective_address_from_modrm(&modrm);
-            if DEBUG {
-                let reg_name = register_name(modrm.reg);
-                println!("add {:08X},{}", address, reg_name);
-            }
-            let result = self.memory_u32(address) + self.register[modrm.reg as usize];
-            self.memory_set32(address, result);
-        } else if modrm.mode == 0b11 {
-            if DEBUG {
-                let reg_name1 = register_name(modrm.rm);
-                let reg_name2 = register_name(modrm.reg);
-                println!("add {},{}", reg_name1, reg_name2);
+    fn pop_r(&mut self, code: u32) -> Result<(), EmulatorError> {
+        let reg = code - 0x58;
+        let value = self.pop_sized(self.operand_size)?;
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("value: {:X}", value);
+        }
+        self.set_register_sized(reg, self.operand_size, value);
+        return Ok(());
+    }
+
+    // 60: the stored ESP is its value before the first push
+    fn pushad(&mut self) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let esp = self.esp();
+        for reg in 0..8 {
+            let value = if reg == ESP as u32 { esp } else { self.register_sized(reg, size) };
+            if let Err(err) = self.push_sized(size, value) {
+                self.register[ESP as usize] = esp;
+                return Err(err);
             }
-            self.register[modrm.rm as usize] += self.register[modrm.reg as usize]
-        } else {
-            unimplemented!("unknown Mod");
         }
+        return Ok(());
     }
 
-    fn sub_eax_imm32(&mut self) {
-        let value = self.code32(0);
-        if DEBUG {
-            println!("sub EAX,{:08X}", value);
+    // 61: the saved ESP is skipped
+    fn popad(&mut self) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let esp = self.esp();
+        let mut values = [0; 8];
+        for i in 0..8 {
+            values[7 - i] = self.memory_sized(esp.wrapping_add(i as u32 * size / 8), size)?;
         }
-        self.register[EAX as usize] -= value;
-        self.epi_add4();
-    }
-
-    fn sub_rm32_r32(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.mode == 0b11 {
-            if DEBUG {
-                let reg_name1 = register_name(modrm.rm);
-                let reg_name2 = register_name(modrm.reg);
-                println!("sub {},{}", reg_name1, reg_name2);
+        for reg in 0..8 {
+            if reg != ESP as u32 {
+                self.set_register_sized(reg, size, values[reg as usize]);
             }
-            self.register[modrm.rm as usize] -= self.register[modrm.reg as usize];
-        } else {
-            unimplemented!("unknown Mod");
         }
+        self.register[ESP as usize] = esp.wrapping_add(8 * (size / 8));
+        return Ok(());
+    }
+
+    // 9C: bit 1 of EFLAGS always reads as set
+    fn pushfd(&mut self) -> Result<(), EmulatorError> {
+        return self.push_sized(self.operand_size, self.eflags | flags::RESERVED);
+    }
+
+    // 9D: only the bits a user-mode program may change are written
+    fn popfd(&mut self) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let value = self.pop_sized(size)?;
+        self.update_flags(value, flags::POPF & flags::mask(size));
+        return Ok(());
+    }
+
+    // C8 iw ib: ENTER with a frame size and nesting level
+    fn enter(&mut self) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let alloc = self.code16(0)?;
+        let level = self.code8(2)? & 0x1f;
+        self.eip = self.eip.wrapping_add(3);
+        let esp = self.esp();
+        let ebp = self.register(EBP as u32);
+        let result = self.enter_frame(size, alloc, level);
+        if result.is_err() {
+            self.register[ESP as usize] = esp;
+            self.register[EBP as usize] = ebp;
+        }
+        return result;
     }
 
-    fn sub_r32_rm32(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.mode == 0b01 {
-            let (reg, address) = self.read_effective_address_from_modrm(&modrm);
-            self.register[reg as usize] -= self.memory_u32(address);
-        } else {
-            unimplemented!("unknown Mod");
+    fn enter_frame(&mut self, size: u32, alloc: u32, level: u32) -> Result<(), EmulatorError> {
+        self.push_sized(size, self.register_sized(EBP as u32, size))?;
+        let frame = self.esp();
+        let mut ebp = self.register(EBP as u32);
+        for _ in 1..level.max(1) {
+            ebp = ebp.wrapping_sub(size / 8);
+            let value = self.memory_sized(ebp, size)?;
+            self.push_sized(size, value)?;
+        }
+        if level > 0 {
+            self.push_sized(size, frame)?;
         }
+        self.set_register_sized(EBP as u32, size, frame);
+        self.register[ESP as usize] = self.esp().wrapping_sub(alloc);
+        return Ok(());
+    }
+
+    // 40-4F, FE /0 /1 and FF /0 /1: CF is left unchanged
+    fn inc_dec(&mut self, operand: Operand, size: u32, dec: bool) -> Result<(), EmulatorError> {
+        let value = self.rm(operand, size)?;
+        let (result, flags) = if dec { flags::dec(value, size) } else { flags::inc(value, size) };
+        self.set_rm(operand, size, result)?;
+        self.update_flags(flags, flags::STATUS & !flags::CF);
+        return Ok(());
     }
 
-    fn mov_r32_rm32(&mut self) {
-        let modrm = self.read_modrm();
-        if modrm.mode == 0b01 {
-            let (reg, address) = self.read_effective_address_from_modrm(&modrm);
-            let reg_name = register_name(reg);
-            if DEBUG {
-                println!("mov {},[{:#X}]", reg_name, address);
+    fn opcode0f(&mut self) -> Result<(), EmulatorError> {
+        let code = self.code8(0)?;
+        self.epi_inc();
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("opcode: {:02X}", code);
+        }
+        if (0x40..=0x4f).contains(&code) {
+            self.cmovcc_r_rm(code - 0x40)?;
+        } else if (0x80..=0x8f).contains(&code) {
+            self.jcc_rel32(code - 0x80)?;
+        } else if (0x90..=0x9f).contains(&code) {
+            self.setcc_rm8(code - 0x90)?;
+        } else if [0xa4, 0xa5, 0xac, 0xad].contains(&code) {
+            self.double_shift(code)?;
+        } else if code == 0xa3 || code == 0xab || code == 0xb3 || code == 0xbb {
+            self.bt_rm_r(code)?;
+        } else if code == 0xaf {
+            self.imul_r_rm(None)?;
+        } else if code == 0xb6 || code == 0xb7 {
+            self.movx_r_rm(if code == 0xb6 { 8 } else { 16 }, false)?;
+        } else if code == 0xba {
+            self.bt_rm_imm()?;
+        } else if code == 0xbc || code == 0xbd {
+            self.bit_scan(code == 0xbd)?;
+        } else if code == 0xbe || code == 0xbf {
+            self.movx_r_rm(if code == 0xbe { 8 } else { 16 }, true)?;
+        } else {
+            return Err(self.unknown_opcode());
+        }
+        return Ok(());
+    }
+
+    // FE and FF: the operation is selected by the ModR/M reg field
+    fn opcodeff(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let modrm = self.read_modrm()?;
+        if modrm.opcode < 2 {
+            let operand = self.read_effective_address_from_modrm(&modrm)?;
+            self.inc_dec(operand, size, modrm.opcode == 1)?;
+        } else if (modrm.opcode == 2 || modrm.opcode == 4) && size != 8 {
+            let operand = self.read_effective_address_from_modrm(&modrm)?;
+            let target = self.rm(operand, size)?;
+            if modrm.opcode == 2 {
+                self.push_sized(size, self.eip)?;
             }
-            let value = self.memory_u32(address);
-            if DEBUG {
-                println!("value: {}", value);
+            if self.tracing(TraceLevel::Decode) {
+                eprintln!("jump => {:08X}", target);
             }
-            self.register[reg as usize] = value;
+            self.eip = target;
+        } else if modrm.opcode == 6 && size != 8 {
+            self.push_rm(modrm)?;
         } else {
-            unimplemented!("unknown Mod");
+            // FF /3 and /5 are far transfers, which need segments
+            return Err(self.unknown_opcode());
         }
+        return Ok(());
     }
 
-    fn mov_r32_imm32(&mut self, code: u32) {
-        let reg = code - 0xb8;
-        let reg_name = register_name(reg);
-        let value = self.code32(0);
-        if DEBUG {
-            println!("mov {},{:#X}", reg_name, value);
+    // C0 and C1 take an imm8 count, D0 and D1 a count of 1, D2 and D3 CL
+    fn shift_rm(&mut self, code: u32, size: u32) -> Result<(), EmulatorError> {
+        let modrm = self.read_modrm()?;
+        let operand = self.read_effective_address_from_modrm(&modrm)?;
+        let count = if code < 0xd0 {
+            let count = self.code8(0)?;
+            self.epi_inc();
+            count
+        } else if code < 0xd2 {
+            1
+        } else {
+            self.register(ECX as u32) & 0xff
+        };
+        // the count is masked to five bits and a zero count changes nothing
+        let count = count & 0x1f;
+        if count == 0 {
+            return Ok(());
         }
-        self.register[reg as usize] = value;
-        self.epi_add4();
-    }
-
-    fn mov_rm32_imm32(&mut self) {
-        let (opcode, address) = self.read_effective_address();
-        if opcode == 0 {
-            let value = self.code32(0);
-            if DEBUG {
-                println!("mov [{:08X}],{:08X}", address, value);
-            }
-            self.epi_add4();
-            self.memory_set32(address, value);
+        let value = self.rm(operand, size)?;
+        let (result, flags) = flags::shift(modrm.opcode, value, count, self.is_carry(), size);
+        self.set_rm(operand, size, result)?;
+        let mask = if modrm.opcode < 4 { flags::CF | flags::OF } else { flags::STATUS };
+        self.update_flags(flags, mask);
+        return Ok(());
+    }
+
+    // 0F A4/A5 (SHLD) and 0F AC/AD (SHRD); the even opcodes take an imm8 count
+    fn double_shift(&mut self, code: u32) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let (reg, operand) = self.read_effective_address()?;
+        let count = if code & 1 == 0 {
+            let count = self.code8(0)?;
+            self.epi_inc();
+            count
         } else {
-            unimplemented!("unknown opcode: {}", opcode);
+            self.register(ECX as u32) & 0xff
+        };
+        let count = count & 0x1f;
+        if count == 0 {
+            return Ok(());
         }
+        let value = self.rm(operand, size)?;
+        let (result, flags) = flags::double_shift(code < 0xac, value, self.register_sized(reg, size), count, size);
+        self.set_rm(operand, size, result)?;
+        self.update_flags(flags, flags::STATUS);
+        return Ok(());
     }
 
-    fn mov_rm32_r32(&mut self) {
-        let modrm = self.read_modrm();
+    pub fn is_carry(&self) -> bool {
+        return (self.eflags & flags::CF) != 0;
+    }
 
-        if modrm.mode == 0b01 {
-            let value = self.register(modrm.reg);
-            let (_reg, address) = self.read_effective_address_from_modrm(&modrm);
-            if DEBUG {
-                print!("mov [{:08X}]", address);
-            }
-            self.memory_set32(address, value);
-        } else if modrm.mode == 0b11 {
-            let reg_name1 = register_name(modrm.reg);
-            let reg_name2 = register_name(modrm.rm);
-            if DEBUG {
-                println!("mov {},{}", reg_name2, reg_name1);
-            }
-            self.register[modrm.rm as usize] = self.register(modrm.reg);
-        } else {
-            unimplemented!("unknown Mod");
-        }
+    pub fn is_parity(&self) -> bool {
+        return (self.eflags & flags::PF) != 0;
     }
 
-    fn call_rel32(&mut self) {
-        let value = self.sign_code32(0);
-        if DEBUG {
-            println!("call {:08X}", value);
-        }
-        self.push32(self.eip as u32 + 4);
-        self.jump(4 + value);
+    pub fn is_auxiliary_carry(&self) -> bool {
+        return (self.eflags & flags::AF) != 0;
     }
 
-    pub fn launch(&mut self) -> Result<(), ()> {
-        println!("--- START ---");
-        loop {
-            if DEBUG {
-                println!("EIP: {:08X}", self.eip);
-            }
+    pub fn is_zero(&self) -> bool {
+        return (self.eflags & flags::ZF) != 0;
+    }
 
-            let code = self.code8(0);
-            self.epi_inc();
+    pub fn is_sign_flag(&self) -> bool {
+        return (self.eflags & flags::SF) != 0;
+    }
 
-            if DEBUG {
-                println!("opcode: {:02X}", code);
-            }
+    pub fn is_overflow(&self) -> bool {
+        return (self.eflags & flags::OF) != 0;
+    }
 
-            if code == 0x01 {
-                self.add_rm32_r32();
-            } else if code == 0x03 {
-                self.add_r32_rm32();
-            } else if code == 0x05 {
-                self.add_eax_imm32();
-            } else if code == 0x0f {
-                self.opcode0f();
-            } else if code == 0x29 {
-                self.sub_rm32_r32();
-            } else if code == 0x2b {
-                self.sub_r32_rm32();
-            } else if code == 0x2d {
-                self.sub_eax_imm32();
-            } else if code == 0x31 {
-                self.xor_rm32_r32();
-            } else if code == 0x3b {
-                self.cmp_r32_rm32();
-            } else if (0x50 <= code) && (code <= (0x50 + 7)) {
-                self.push_r32(code);
-            } else if (0x58 <= code) && (code <= (0x58 + 7)) {
-                self.pop_r32(code);
-            } else if code == 0x6a {
-                self.push_imm8();
-            } else if code == 0x74 {
-                self.jz_rel8();
-            } else if code == 0x75 {
-                self.jnz_rel8();
-            } else if code == 0x7e {
-                self.jng_rel8();
-            } else if code == 0x7f {
-                self.jg_rel8();
-            } else if code == 0x81 {
-                self.opcode81();
-            } else if code == 0x83 {
-                self.opcode83();
-            } else if code == 0x89 {
-                self.mov_rm32_r32();
-            } else if code == 0x8b {
-                self.mov_r32_rm32();
-            } else if code == 0x8d {
-                self.lea();
-            } else if code == 0x90 {
-                self.nop();
-            } else if code == 0xd1 {
-                self.opcoded1();
-            } else if code == 0xff {
-                self.opcodeff();
-            } else if code == 0xc9 {
-                self.leave();
-            } else if code == 0xc7 {
-                self.mov_rm32_imm32();
-            } else if code == 0xeb {
-                self.jump_short();
-            } else if code == 0xe8 {
-                self.call_rel32();
-            } else if (0xb8 <= code) && (code <= (0xb8 + 7)) {
-                self.mov_r32_imm32(code);
-            } else if code == 0xc3 {
-                if DEBUG {
-                    println!("ret");
-                }
-                let address = self.pop32();
-                if DEBUG {
-                    println!("ret => address: {:08X}", address);
-                }
-                if address == 0 {
-                    println!("--- EXIT ---");
-                    return Ok(());
-                } else {
-                    self.eip = address;
-                }
-            } else {
-                unimplemented!("unknown code: {:02X}", code);
-            }
-            // self.dump_register();
-            if DEBUG {
-                println!("---");
-            }
-        }
-        panic!("main loop ends without ret");
+    pub fn is_direction(&self) -> bool {
+        return (self.eflags & flags::DF) != 0;
     }
 
-    pub fn dump_memory(&self) {
-        for i in 0..20 {
-            let index = (4 * i) as usize;
-            let mut data: String = String::new();
-            for j in 0..4 {
-                let str1 = format!("{:02X}", self.memory[index+j]);
-                data.push_str(&str1);
+    // evaluates the condition code in the low four bits of Jcc, SETcc and CMOVcc
+    fn condition(&self, cc: u32) -> bool {
+        let result = match cc >> 1 {
+            0 => self.is_overflow(),
+            1 => self.is_carry(),
+            2 => self.is_zero(),
+            3 => self.is_carry() || self.is_zero(),
+            4 => self.is_sign_flag(),
+            5 => self.is_parity(),
+            6 => self.is_sign_flag() != self.is_overflow(),
+            _ => self.is_zero() || (self.is_sign_flag() != self.is_overflow())
+        };
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("eflags = {:032b}", self.eflags);
+        }
+        // odd condition codes are the negation of the even ones
+        return result != (cc & 1 == 1);
+    }
+
+    // 70-7F
+    fn jcc_rel8(&mut self, cc: u32) -> Result<(), EmulatorError> {
+        let value = self.sign_code8(0)?;
+        self.epi_inc();
+        if self.condition(cc) {
+            self.jump(value);
+        };
+        return Ok(());
+    }
+
+    // 0F 80-8F
+    fn jcc_rel32(&mut self, cc: u32) -> Result<(), EmulatorError> {
+        let value = self.sign_code32(0)?;
+        self.epi_add4();
+        if self.condition(cc) {
+            self.jump(value);
+        };
+        return Ok(());
+    }
+
+    // 0F 90-9F
+    fn setcc_rm8(&mut self, cc: u32) -> Result<(), EmulatorError> {
+        let (_, operand) = self.read_effective_address()?;
+        let value = self.condition(cc) as u32;
+        self.set_rm(operand, 8, value)?;
+        return Ok(());
+    }
+
+    // 0F 40-4F
+    fn cmovcc_r_rm(&mut self, cc: u32) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let (reg, operand) = self.read_effective_address()?;
+        let value = self.rm(operand, size)?;
+        if self.condition(cc) {
+            self.set_register_sized(reg, size, value);
+        }
+        return Ok(());
+    }
+
+    fn update_flags(&mut self, value: u32, mask: u32) {
+        self.eflags = (self.eflags & !mask) | (value & mask);
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("eflags = {:032b}", self.eflags);
+        }
+    }
+
+    fn cmp_base(&mut self, target: u32, value: u32, size: u32) {
+        let (result, flags) = flags::sub(target, value, false, size);
+        self.update_flags(flags, flags::STATUS);
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("result {}, {:08X}", result, result);
+        }
+    }
+
+    #[cfg(test)]
+    fn cmp_u32_u32(&mut self, target: u32, value: u32) {
+        self.cmp_base(target, value, 32);
+    }
+
+    #[cfg(test)]
+    fn cmp_u32_i32(&mut self, target: u32, sign_value: i32) {
+        self.cmp_base(target, sign_value as u32, 32);
+    }
+
+    // returns the result to write back; CMP only updates the flags
+    fn alu(&mut self, op: u32, target: u32, value: u32, size: u32) -> Result<Option<u32>, EmulatorError> {
+        let (result, flags) = if op == ALU_ADD {
+            flags::add(target, value, false, size)
+        } else if op == ALU_OR {
+            let result = target | value;
+            (result, flags::logic(result, size))
+        } else if op == ALU_ADC {
+            flags::add(target, value, self.is_carry(), size)
+        } else if op == ALU_SBB {
+            flags::sub(target, value, self.is_carry(), size)
+        } else if op == ALU_AND {
+            let result = target & value;
+            (result, flags::logic(result, size))
+        } else if op == ALU_SUB {
+            flags::sub(target, value, false, size)
+        } else if op == ALU_XOR {
+            let result = target ^ value;
+            (result, flags::logic(result, size))
+        } else if op == ALU_CMP {
+            self.cmp_base(target, value, size);
+            return Ok(None);
+        } else {
+            return Err(self.unknown_opcode());
+        };
+        self.update_flags(flags, flags::STATUS);
+        return Ok(Some(result));
+    }
+
+    fn alu_rm_r(&mut self, op: u32, size: u32) -> Result<(), EmulatorError> {
+        let (reg, operand) = self.read_effective_address()?;
+        let value = self.register_sized(reg, size);
+        if let Some(result) = self.alu(op, self.rm(operand, size)?, value, size)? {
+            self.set_rm(operand, size, result)?;
+        }
+        return Ok(());
+    }
+
+    fn alu_r_rm(&mut self, op: u32, size: u32) -> Result<(), EmulatorError> {
+        let (reg, operand) = self.read_effective_address()?;
+        let value = self.rm(operand, size)?;
+        if let Some(result) = self.alu(op, self.register_sized(reg, size), value, size)? {
+            self.set_register_sized(reg, size, result);
+        }
+        return Ok(());
+    }
+
+    fn alu_acc_imm(&mut self, op: u32, size: u32) -> Result<(), EmulatorError> {
+        let value = self.read_imm(size)?;
+        if let Some(result) = self.alu(op, self.register_sized(0, size), value, size)? {
+            self.set_register_sized(0, size, result);
+        }
+        return Ok(());
+    }
+
+    // 80, 81 and 83: the operation is selected by the ModR/M reg field
+    fn alu_rm_imm(&mut self, size: u32, imm_size: u32) -> Result<(), EmulatorError> {
+        let modrm = self.read_modrm()?;
+        let operand = self.read_effective_address_from_modrm(&modrm)?;
+        let value = if imm_size == size {
+            self.read_imm(size)?
+        } else {
+            self.read_simm8(size)?
+        };
+        if let Some(result) = self.alu(modrm.opcode, self.rm(operand, size)?, value, size)? {
+            self.set_rm(operand, size, result)?;
+        }
+        return Ok(());
+    }
+
+    fn test_rm_r(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let (reg, operand) = self.read_effective_address()?;
+        let result = self.rm(operand, size)? & self.register_sized(reg, size);
+        self.update_flags(flags::logic(result, size), flags::STATUS);
+        return Ok(());
+    }
+
+    fn test_acc_imm(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let value = self.read_imm(size)?;
+        let result = self.register_sized(0, size) & value;
+        self.update_flags(flags::logic(result, size), flags::STATUS);
+        return Ok(());
+    }
+
+    // F6 and F7: the operation is selected by the ModR/M reg field
+    fn opcodef7(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let modrm = self.read_modrm()?;
+        let operand = self.read_effective_address_from_modrm(&modrm)?;
+        let value = self.rm(operand, size)?;
+        if modrm.opcode < 2 {
+            let result = value & self.read_imm(size)?;
+            self.update_flags(flags::logic(result, size), flags::STATUS);
+        } else if modrm.opcode == 2 {
+            self.set_rm(operand, size, !value)?;
+        } else if modrm.opcode == 3 {
+            let (result, flags) = flags::neg(value, size);
+            self.set_rm(operand, size, result)?;
+            self.update_flags(flags, flags::STATUS);
+        } else if modrm.opcode == 4 || modrm.opcode == 5 {
+            let accumulator = self.register_sized(EAX as u32, size);
+            let (product, flags) = if modrm.opcode == 4 {
+                flags::mul(accumulator, value, size)
+            } else {
+                flags::imul(accumulator, value, size)
+            };
+            self.set_acc_pair(size, product);
+            self.update_flags(flags, flags::STATUS);
+        } else {
+            self.div(value, size, modrm.opcode == 7)?;
+        }
+        return Ok(());
+    }
+
+    // AX for byte operands, otherwise DX:AX or EDX:EAX
+    fn acc_pair(&self, size: u32) -> u64 {
+        if size == 8 {
+            return self.register_sized(EAX as u32, 16) as u64;
+        }
+        let high = self.register_sized(EDX as u32, size) as u64;
+        return high << size | self.register_sized(EAX as u32, size) as u64;
+    }
+
+    fn set_acc_pair(&mut self, size: u32, value: u64) {
+        if size == 8 {
+            self.set_register_sized(EAX as u32, 16, value as u32);
+        } else {
+            self.set_register_sized(EAX as u32, size, value as u32);
+            self.set_register_sized(EDX as u32, size, (value >> size) as u32);
+        }
+    }
+
+    // DIV and IDIV leave the quotient in AL/AX/EAX and the remainder in
+    // AH/DX/EDX; the flags are undefined and left unchanged.
+    fn div(&mut self, divisor: u32, size: u32, signed: bool) -> Result<(), EmulatorError> {
+        let dividend = self.acc_pair(size);
+        let (quotient, remainder) = if signed {
+            let dividend = if size == 32 { dividend as i64 } else { flags::sign_extend(dividend as u32, 2 * size) };
+            let divisor = flags::sign_extend(divisor, size);
+            let quotient = match dividend.checked_div(divisor) {
+                Some(quotient) if quotient == flags::sign_extend(quotient as u32, size) => quotient,
+                _ => return Err(self.divide_error())
+            };
+            (quotient as u32, (dividend % divisor) as u32)
+        } else {
+            let divisor = (divisor & flags::mask(size)) as u64;
+            if divisor == 0 || dividend / divisor > flags::mask(size) as u64 {
+                return Err(self.divide_error());
+            }
+            ((dividend / divisor) as u32, (dividend % divisor) as u32)
+        };
+        if size == 8 {
+            self.set_register_sized(EAX as u32, 16, (quotient & 0xff) | (remainder & 0xff) << 8);
+        } else {
+            self.set_register_sized(EAX as u32, size, quotient);
+            self.set_register_sized(EDX as u32, size, remainder);
+        }
+        return Ok(());
+    }
+
+    // 0F AF, 69 and 6B
+    fn imul_r_rm(&mut self, imm_size: Option<u32>) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let (reg, operand) = self.read_effective_address()?;
+        let value = self.rm(operand, size)?;
+        let (target, value) = match imm_size {
+            Some(8) => (value, self.read_simm8(size)?),
+            Some(_) => (value, self.read_imm(size)?),
+            None => (self.register_sized(reg, size), value)
+        };
+        let (product, flags) = flags::imul(target, value, size);
+        self.set_register_sized(reg, size, product as u32);
+        self.update_flags(flags, flags::STATUS);
+        return Ok(());
+    }
+
+    // 99: CWD and CDQ copy the sign of AX or EAX into DX or EDX
+    fn cdq(&mut self) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let sign = flags::sign_extend(self.register_sized(EAX as u32, size), size) < 0;
+        self.set_register_sized(EDX as u32, size, if sign { 0xffffffff } else { 0 });
+        return Ok(());
+    }
+
+    // C3, and C2 which also releases `release` bytes of arguments
+    fn ret(&mut self, release: u32) -> Result<Option<StopReason>, EmulatorError> {
+        let address = self.pop32()?;
+        self.register[ESP as usize] = self.esp().wrapping_add(release);
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("ret => address: {:08X}", address);
+        }
+        if Some(address) == self.exit_address {
+            return Ok(Some(StopReason::Exit(self.get_register(EAX))));
+        }
+        self.eip = address;
+        return Ok(None);
+    }
+
+    // MOVS, CMPS, STOS, LODS and SCAS. With a REP prefix each step runs
+    // one iteration and rewinds EIP until ECX runs out (or, for CMPS and
+    // SCAS, ZF ends a REPE or REPNE), so a long copy can be interrupted.
+    fn string(&mut self, code: u32, size: u32) -> Result<(), EmulatorError> {
+        if self.rep_prefix.is_some() && self.register(ECX as u32) == 0 {
+            return Ok(());
+        }
+        let delta = if self.is_direction() { (size / 8).wrapping_neg() } else { size / 8 };
+        let source = self.register(ESI as u32);
+        let destination = self.register(EDI as u32);
+        let op = code & !1;
+        if op == 0xa4 {
+            let value = self.memory_sized(source, size)?;
+            self.set_memory_sized(destination, size, value)?;
+        } else if op == 0xa6 {
+            let value = self.memory_sized(destination, size)?;
+            self.cmp_base(self.memory_sized(source, size)?, value, size);
+        } else if op == 0xaa {
+            self.set_memory_sized(destination, size, self.register_sized(EAX as u32, size))?;
+        } else if op == 0xac {
+            let value = self.memory_sized(source, size)?;
+            self.set_register_sized(EAX as u32, size, value);
+        } else {
+            let value = self.memory_sized(destination, size)?;
+            self.cmp_base(self.register_sized(EAX as u32, size), value, size);
+        }
+        if op == 0xa4 || op == 0xa6 || op == 0xac {
+            self.set_register(ESI, source.wrapping_add(delta));
+        }
+        if op != 0xac {
+            self.set_register(EDI, destination.wrapping_add(delta));
+        }
+
+        if let Some(prefix) = self.rep_prefix {
+            let count = self.register(ECX as u32).wrapping_sub(1);
+            self.set_register(ECX, count);
+            let compare = op == 0xa6 || op == 0xae;
+            let ended = compare && self.is_zero() != (prefix == 0xf3);
+            if count != 0 && !ended {
+                self.eip = self.instruction_eip;
+                self.repeating = true;
+            }
+        }
+        return Ok(());
+    }
+
+    // There are no devices; only the exit port does anything.
+    fn out(&mut self, port: u32, size: u32) -> Result<Option<StopReason>, EmulatorError> {
+        let value = self.register_sized(EAX as u32, size);
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("out => port: {:04X}, value: {:08X}", port, value);
+        }
+        if Some(port as u16) == self.exit_port {
+            return Ok(Some(StopReason::Exit(value)));
+        }
+        return Ok(None);
+    }
+
+    fn nop(&mut self) -> Result<(), EmulatorError> {
+        return Ok(());
+    }
+
+    fn lea(&mut self) -> Result<(), EmulatorError> {
+        let (reg, operand) = self.read_effective_address()?;
+        if let Operand::Memory(address) = operand {
+            self.set_register_sized(reg, self.operand_size, address);
+        } else {
+            return Err(self.unsupported_addressing());
+        }
+        return Ok(());
+    }
+
+    fn mov_r_rm(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let (reg, operand) = self.read_effective_address()?;
+        let value = self.rm(operand, size)?;
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("value: {}", value);
+        }
+        self.set_register_sized(reg, size, value);
+        return Ok(());
+    }
+
+    // B0+r and B8+r
+    fn mov_r_imm(&mut self, reg: u32, size: u32) -> Result<(), EmulatorError> {
+        let value = self.read_imm(size)?;
+        self.set_register_sized(reg, size, value);
+        return Ok(());
+    }
+
+    fn mov_rm_imm(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let (opcode, operand) = self.read_effective_address()?;
+        if opcode == 0 {
+            let value = self.read_imm(size)?;
+            self.set_rm(operand, size, value)?;
+        } else {
+            return Err(self.unknown_opcode());
+        }
+        return Ok(());
+    }
+
+    fn mov_rm_r(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let (reg, operand) = self.read_effective_address()?;
+        self.set_rm(operand, size, self.register_sized(reg, size))?;
+        return Ok(());
+    }
+
+    // A0-A3: the operand is a 32-bit absolute address after the opcode
+    fn mov_acc_moffs(&mut self, code: u32) -> Result<(), EmulatorError> {
+        let size = if code & 1 == 0 { 8 } else { self.operand_size };
+        let address = self.read_imm(32)?;
+        if code < 0xa2 {
+            let value = self.memory_sized(address, size)?;
+            self.set_register_sized(EAX as u32, size, value);
+        } else {
+            self.set_memory_sized(address, size, self.register_sized(EAX as u32, size))?;
+        }
+        return Ok(());
+    }
+
+    // 0F B6/B7 (MOVZX) and 0F BE/BF (MOVSX)
+    fn movx_r_rm(&mut self, rm_size: u32, signed: bool) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let (reg, operand) = self.read_effective_address()?;
+        let mut value = self.rm(operand, rm_size)?;
+        if signed {
+            value = flags::sign_extend(value, rm_size) as u32;
+        }
+        self.set_register_sized(reg, size, value & flags::mask(size));
+        return Ok(());
+    }
+
+    fn xchg_rm_r(&mut self, size: u32) -> Result<(), EmulatorError> {
+        let (reg, operand) = self.read_effective_address()?;
+        let value = self.rm(operand, size)?;
+        self.set_rm(operand, size, self.register_sized(reg, size))?;
+        self.set_register_sized(reg, size, value);
+        return Ok(());
+    }
+
+    // 91-97: exchange with eAX
+    fn xchg_acc_r(&mut self, reg: u32) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let value = self.register_sized(reg, size);
+        self.set_register_sized(reg, size, self.register_sized(EAX as u32, size));
+        self.set_register_sized(EAX as u32, size, value);
+        return Ok(());
+    }
+
+    // 98: CBW with a 16-bit operand, CWDE otherwise
+    fn cwde(&mut self) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let value = flags::sign_extend(self.register_sized(EAX as u32, size / 2), size / 2) as u32;
+        self.set_register_sized(EAX as u32, size, value);
+        return Ok(());
+    }
+
+    // D7: AL = [EBX + AL]
+    fn xlat(&mut self) -> Result<(), EmulatorError> {
+        let address = self.register(EBX as u32).wrapping_add(self.register_sized(EAX as u32, 8));
+        let value = self.memory_sized(address, 8)?;
+        self.set_register_sized(EAX as u32, 8, value);
+        return Ok(());
+    }
+
+    // 0F A3/AB/B3/BB: with a memory operand the register offset is signed
+    // and selects a bit anywhere relative to the addressed operand
+    fn bt_rm_r(&mut self, code: u32) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let (reg, operand) = self.read_effective_address()?;
+        let offset = flags::sign_extend(self.register_sized(reg, size), size);
+        let operand = match operand {
+            Operand::Memory(address) => {
+                let displacement = offset.div_euclid(size as i64) * (size / 8) as i64;
+                Operand::Memory(address.wrapping_add(displacement as u32))
+            }
+            register => register
+        };
+        return self.bit_test((code >> 3) & 3, operand, offset as u32 & (size - 1), size);
+    }
+
+    // 0F BA /4-/7: the imm8 offset wraps within the operand
+    fn bt_rm_imm(&mut self) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let modrm = self.read_modrm()?;
+        if modrm.opcode < 4 {
+            return Err(self.unknown_opcode());
+        }
+        let operand = self.read_effective_address_from_modrm(&modrm)?;
+        let offset = self.read_imm(8)?;
+        return self.bit_test(modrm.opcode - 4, operand, offset & (size - 1), size);
+    }
+
+    // op 0 BT, 1 BTS, 2 BTR, 3 BTC: CF receives the bit before it is changed
+    fn bit_test(&mut self, op: u32, operand: Operand, bit: u32, size: u32) -> Result<(), EmulatorError> {
+        let value = self.rm(operand, size)?;
+        let mask = 1 << bit;
+        if op == 1 {
+            self.set_rm(operand, size, value | mask)?;
+        } else if op == 2 {
+            self.set_rm(operand, size, value & !mask)?;
+        } else if op == 3 {
+            self.set_rm(operand, size, value ^ mask)?;
+        }
+        self.update_flags(if value & mask != 0 { flags::CF } else { 0 }, flags::CF);
+        return Ok(());
+    }
+
+    // 0F BC (BSF) and 0F BD (BSR): a zero source sets ZF and leaves the destination alone
+    fn bit_scan(&mut self, reverse: bool) -> Result<(), EmulatorError> {
+        let size = self.operand_size;
+        let (reg, operand) = self.read_effective_address()?;
+        let value = self.rm(operand, size)?;
+        if value == 0 {
+            self.update_flags(flags::ZF, flags::ZF);
+        } else {
+            let index = if reverse { 31 - value.leading_zeros() } else { value.trailing_zeros() };
+            self.set_register_sized(reg, size, index);
+            self.update_flags(0, flags::ZF);
+        }
+        return Ok(());
+    }
+
+    fn call_rel32(&mut self) -> Result<(), EmulatorError> {
+        let value = self.sign_code32(0)?;
+        self.epi_add4();
+        self.push32(self.eip)?;
+        self.jump(value);
+        return Ok(());
+    }
+
+    pub fn step(&mut self) -> Result<Option<StopReason>, EmulatorError> {
+        self.instruction_eip = self.eip;
+        self.watch_hit.set(None);
+        self.repeating = false;
+        let result = self.execute();
+        match result {
+            Ok(_) => self.instruction_count += 1,
+            Err(_) => self.eip = self.instruction_eip
+        }
+        // data watchpoints trap after the instruction completes
+        if let (Ok(None), Some(watchpoint)) = (&result, self.watch_hit.take()) {
+            return Ok(Some(StopReason::Watchpoint(watchpoint)));
+        }
+        return result;
+    }
+
+    fn execute(&mut self) -> Result<Option<StopReason>, EmulatorError> {
+        if self.tracing(TraceLevel::Instructions) {
+            match self.disassemble(self.eip) {
+                Some(insn) => eprintln!("{}", insn),
+                None => eprintln!("{:08X}: (bad)", self.eip)
+            }
+        }
+
+        self.operand_size = 32;
+        self.rep_prefix = None;
+        let mut code = self.code8(0)?;
+        self.epi_inc();
+        while code == 0x66 || code == 0xf2 || code == 0xf3 {
+            if code == 0x66 {
+                self.operand_size = 16;
+            } else {
+                self.rep_prefix = Some(code);
             }
-            println!("{:08X} : {}", index, data);
+            code = self.code8(0)?;
+            self.epi_inc();
+        }
+        let size = self.operand_size;
+
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("opcode: {:02X}", code);
+        }
+
+        // 00-05, 08-0D, ... 38-3D: ALU op in bits 3-5
+        if code < 0x40 && code & 0x07 < 6 {
+            let op = code >> 3;
+            let form = code & 0x07;
+            if form == 0 {
+                self.alu_rm_r(op, 8)?;
+            } else if form == 1 {
+                self.alu_rm_r(op, size)?;
+            } else if form == 2 {
+                self.alu_r_rm(op, 8)?;
+            } else if form == 3 {
+                self.alu_r_rm(op, size)?;
+            } else if form == 4 {
+                self.alu_acc_imm(op, 8)?;
+            } else {
+                self.alu_acc_imm(op, size)?;
+            }
+        } else if code == 0x0f {
+            self.opcode0f()?;
+        } else if (0x40..=0x4f).contains(&code) {
+            self.inc_dec(Operand::Register(code & 7), size, code >= 0x48)?;
+        } else if (0x50..=0x50 + 7).contains(&code) {
+            self.push_r(code)?;
+        } else if (0x58..=0x58 + 7).contains(&code) {
+            self.pop_r(code)?;
+        } else if code == 0x60 {
+            self.pushad()?;
+        } else if code == 0x61 {
+            self.popad()?;
+        } else if code == 0x69 {
+            self.imul_r_rm(Some(size))?;
+        } else if code == 0x68 {
+            self.push_imm(size)?;
+        } else if code == 0x6a {
+            self.push_imm(8)?;
+        } else if code == 0x6b {
+            self.imul_r_rm(Some(8))?;
+        } else if (0x70..=0x7f).contains(&code) {
+            self.jcc_rel8(code - 0x70)?;
+        } else if code == 0x80 || code == 0x82 {
+            self.alu_rm_imm(8, 8)?;
+        } else if code == 0x81 {
+            self.alu_rm_imm(size, size)?;
+        } else if code == 0x83 {
+            self.alu_rm_imm(size, 8)?;
+        } else if code == 0x84 {
+            self.test_rm_r(8)?;
+        } else if code == 0x85 {
+            self.test_rm_r(size)?;
+        } else if code == 0x86 {
+            self.xchg_rm_r(8)?;
+        } else if code == 0x87 {
+            self.xchg_rm_r(size)?;
+        } else if code == 0x88 {
+            self.mov_rm_r(8)?;
+        } else if code == 0x89 {
+            self.mov_rm_r(size)?;
+        } else if code == 0x8a {
+            self.mov_r_rm(8)?;
+        } else if code == 0x8b {
+            self.mov_r_rm(size)?;
+        } else if code == 0x8d {
+            self.lea()?;
+        } else if code == 0x90 {
+            self.nop()?;
+        } else if (0x91..=0x97).contains(&code) {
+            self.xchg_acc_r(code - 0x90)?;
+        } else if code == 0x98 {
+            self.cwde()?;
+        } else if code == 0x99 {
+            self.cdq()?;
+        } else if code == 0xc0 || code == 0xd0 || code == 0xd2 {
+            self.shift_rm(code, 8)?;
+        } else if code == 0xc1 || code == 0xd1 || code == 0xd3 {
+            self.shift_rm(code, size)?;
+        } else if code == 0x8f {
+            self.pop_rm()?;
+        } else if code == 0x9c {
+            self.pushfd()?;
+        } else if code == 0x9d {
+            self.popfd()?;
+        } else if code == 0xc8 {
+            self.enter()?;
+        } else if code == 0xfe {
+            self.opcodeff(8)?;
+        } else if code == 0xff {
+            self.opcodeff(size)?;
+        } else if code == 0xc9 {
+            self.leave()?;
+        } else if (0xa0..=0xa3).contains(&code) {
+            self.mov_acc_moffs(code)?;
+        } else if (0xa4..=0xa7).contains(&code) || (0xaa..=0xaf).contains(&code) {
+            self.string(code, if code & 1 == 0 { 8 } else { size })?;
+        } else if code == 0xa8 {
+            self.test_acc_imm(8)?;
+        } else if code == 0xa9 {
+            self.test_acc_imm(size)?;
+        } else if code == 0xc6 {
+            self.mov_rm_imm(8)?;
+        } else if code == 0xc7 {
+            self.mov_rm_imm(size)?;
+        } else if code == 0xeb {
+            self.jump_short()?;
+        } else if code == 0xe8 {
+            self.call_rel32()?;
+        } else if code == 0xe9 {
+            self.jump_near()?;
+        } else if (0xe0..=0xe3).contains(&code) {
+            self.loop_rel8(code)?;
+        } else if (0xb0..=0xb0 + 7).contains(&code) {
+            self.mov_r_imm(code - 0xb0, 8)?;
+        } else if (0xb8..=0xb8 + 7).contains(&code) {
+            self.mov_r_imm(code - 0xb8, size)?;
+        } else if code == 0xc2 {
+            let release = self.read_imm(16)?;
+            if let Some(reason) = self.ret(release)? {
+                return Ok(Some(reason));
+            }
+        } else if code == 0xc3 {
+            if let Some(reason) = self.ret(0)? {
+                return Ok(Some(reason));
+            }
+        } else if code == 0xe6 || code == 0xe7 {
+            let port = self.code8(0)?;
+            self.epi_inc();
+            if let Some(reason) = self.out(port, if code == 0xe6 { 8 } else { size })? {
+                return Ok(Some(reason));
+            }
+        } else if code == 0xee || code == 0xef {
+            let port = self.get_register(EDX) & 0xffff;
+            if let Some(reason) = self.out(port, if code == 0xee { 8 } else { size })? {
+                return Ok(Some(reason));
+            }
+        } else if code == 0xcd {
+            let vector = self.code8(0)?;
+            self.epi_inc();
+            if vector == 0x80 {
+                if let Some(reason) = self.syscall()? {
+                    return Ok(Some(reason));
+                }
+            } else {
+                return Err(self.unknown_opcode());
+            }
+        } else if code == 0xd7 {
+            self.xlat()?;
+        } else if code == 0xf4 {
+            return Ok(Some(StopReason::Halt));
+        } else if code == 0xfc || code == 0xfd {
+            self.update_flags(if code == 0xfd { flags::DF } else { 0 }, flags::DF);
+        } else if code == 0xf6 {
+            self.opcodef7(8)?;
+        } else if code == 0xf7 {
+            self.opcodef7(size)?;
+        } else {
+            return Err(self.unknown_opcode());
+        }
+        // self.dump_register();
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("---");
+        }
+        return Ok(None);
+    }
+
+    pub fn launch(&mut self) -> Result<StopReason, EmulatorError> {
+        if self.tracing(TraceLevel::Instructions) {
+            eprintln!("--- START ---");
+        }
+        let mut steps: u64 = 0;
+        loop {
+            if let Some(limit) = self.step_limit {
+                if steps >= limit {
+                    return Ok(StopReason::StepLimit);
+                }
+            }
+            // a breakpoint at the resume address must not stop us again,
+            // nor one on a REP instruction between its iterations
+            if steps > 0 && !self.repeating && self.breakpoints.contains(&self.eip) {
+                return Ok(StopReason::Breakpoint(self.eip));
+            }
+            if let Some(reason) = self.step()? {
+                if matches!(reason, StopReason::Exit(_)) && self.tracing(TraceLevel::Instructions) {
+                    eprintln!("--- EXIT ---");
+                }
+                return Ok(reason);
+            }
+            steps += 1;
+        }
+    }
+
+    pub fn dump_memory(&self) {
+        for i in 0..20 {
+            self.dump_word(4 * i);
         }
         println!("---");
         for i in 1..10 {
-            let index = (MEMORY_SIZE - 4 * i) as usize;
-            let mut data: String = String::new();
-            for j in 0..4 {
-                let str1 = format!("{:02X}", self.memory[index+j]);
-                data.push_str(&str1);
+            if let Some(address) = self.memory_size().checked_sub(4 * i) {
+                self.dump_word(address);
             }
-            println!("{:08X} : {}", index, data);
         }
     }
 
+    fn dump_word(&self, address: u32) {
+        if let Ok(bytes) = self.memory_slice(address, 4) {
+            let data: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            println!("{:08X} : {}", address, data.concat());
+        }
+    }
+
+
     pub fn dump_register(&self) {
         for i in 0..self.register.len() {
             let reg_name = register_name(i as u32);
@@ -970,7 +1764,11 @@ impl Emulator {
 #[cfg(test)]
 mod tests {
     const TEST_MEMSIZE: u32 = 1024;
-    use super::Emulator;
+    use std::io;
+    use super::{Emulator, EmulatorError, Register, Register8, Register16, StopReason, WatchKind, Watchpoint,
+                ALU_NAME, CONDITION_NAME};
+    use super::flags::{AF, CF, OF, PF, SF, ZF};
+    use super::Register::*;
 
     #[test]
     fn emulator_new() {
@@ -979,6 +1777,207 @@ mod tests {
         assert_eq!(emu.eflags, 0);
     }
 
+    #[test]
+    fn tiny_memory() {
+        let emu = Emulator::new(3);
+        assert_eq!(emu.get_register(ESP), 0);
+        assert!(emu.memory_u32(0).is_err());
+    }
+
+    #[test]
+    fn register_accessor() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        assert_eq!(emu.get_register(ESP), TEST_MEMSIZE - 4);
+        emu.set_register(EBX, 0x12345678);
+        assert_eq!(emu.get_register(EBX), 0x12345678);
+        assert_eq!(emu.register(3), 0x12345678);
+    }
+
+    #[test]
+    fn memory_accessor() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0x10, &[0x78, 0x56, 0x34, 0x12]).unwrap();
+        assert_eq!(emu.memory_u32(0x10), Ok(0x12345678));
+        assert_eq!(emu.memory_u8(0x13), Ok(0x12));
+        emu.memory_set32(0x20, 0xdeadbeef).unwrap();
+        assert_eq!(emu.memory_slice(0x20, 4), Ok(&[0xef, 0xbe, 0xad, 0xde][..]));
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        let err = emu.memory_set32(TEST_MEMSIZE - 2, 0).unwrap_err();
+        assert_eq!(err, EmulatorError::MemoryOutOfBounds {
+            eip: 0, bytes: vec![], address: TEST_MEMSIZE - 2, size: 4
+        });
+        assert!(emu.memory_u8(TEST_MEMSIZE).is_err());
+        assert!(emu.load(TEST_MEMSIZE - 1, &[0, 0]).is_err());
+    }
+
+    #[test]
+    fn step() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // mov eax, 0x43252; ret
+        emu.load(0, &[0xb8, 0x52, 0x32, 0x04, 0x00, 0xc3]).unwrap();
+        assert_eq!(emu.step(), Ok(None));
+        assert_eq!(emu.eip(), 5);
+        assert_eq!(emu.get_register(EAX), 0x43252);
+        assert_eq!(emu.step(), Ok(Some(StopReason::Exit(0x43252))));
+        assert_eq!(emu.instruction_count(), 2);
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // nop; (undefined) 0F FF
+        emu.load(0, &[0x90, 0x0f, 0xff]).unwrap();
+        let err = emu.launch().unwrap_err();
+        assert_eq!(err, EmulatorError::UnknownOpcode { eip: 1, bytes: vec![0x0f, 0xff] });
+        assert_eq!(emu.eip(), 1);
+    }
+
+    #[test]
+    fn unsupported_addressing() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // lea eax,eax
+        emu.load(0, &[0x8d, 0xc0]).unwrap();
+        let err = emu.launch().unwrap_err();
+        assert_eq!(err, EmulatorError::UnsupportedAddressing { eip: 0, bytes: vec![0x8d, 0xc0] });
+    }
+
+    #[test]
+    fn launch_halt() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0x90, 0xf4]).unwrap();
+        assert_eq!(emu.launch(), Ok(StopReason::Halt));
+        assert_eq!(emu.eip(), 2);
+    }
+
+    #[test]
+    fn exit_address() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // mov eax,7; ret
+        emu.load(0x100, &[0xb8, 0x07, 0x00, 0x00, 0x00, 0xc3]).unwrap();
+        emu.set_eip(0x100);
+        emu.set_exit_address(Some(0xdead));
+        emu.store_exit_address().unwrap();
+        assert_eq!(emu.memory_u32(emu.get_register(ESP)), Ok(0xdead));
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(7)));
+
+        // without an exit address, ret 0 is an ordinary jump
+        emu.set_eip(0x100);
+        emu.set_exit_address(None);
+        emu.push32(0).unwrap();
+        emu.set_step_limit(Some(2));
+        assert_eq!(emu.launch(), Ok(StopReason::StepLimit));
+        assert_eq!(emu.eip(), 0);
+    }
+
+    #[test]
+    fn exit_port() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // mov al,3; out 0x80,al; mov dx,0xf4; mov eax,0x105; out dx,eax
+        emu.load(0, &[0xb0, 0x03, 0xe6, 0x80, 0x66, 0xba, 0xf4, 0x00,
+                      0xb8, 0x05, 0x01, 0x00, 0x00, 0xef]).unwrap();
+        emu.set_exit_port(Some(0xf4));
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(0x105)));
+        assert_eq!(emu.eip(), 14);
+    }
+
+    #[test]
+    fn launch_breakpoint() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0x90, 0x90, 0x90, 0xf4]).unwrap();
+        emu.add_breakpoint(2);
+        assert_eq!(emu.launch(), Ok(StopReason::Breakpoint(2)));
+        assert_eq!(emu.eip(), 2);
+        assert_eq!(emu.launch(), Ok(StopReason::Halt));
+    }
+
+    #[test]
+    fn launch_step_limit() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // jmp short $
+        emu.load(0, &[0xeb, 0xfe]).unwrap();
+        emu.set_step_limit(Some(10));
+        assert_eq!(emu.launch(), Ok(StopReason::StepLimit));
+        assert_eq!(emu.instruction_count(), 10);
+    }
+
+    #[test]
+    fn stack_and_jump_wrap() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // push eax with ESP at 0; the faulting push leaves ESP as it was
+        emu.load(0, &[0x50]).unwrap();
+        emu.set_register(ESP, 0);
+        assert_eq!(emu.step(), Err(EmulatorError::MemoryOutOfBounds {
+            eip: 0, bytes: vec![0x50], address: 0xfffffffc, size: 4
+        }));
+        assert_eq!(emu.get_register(ESP), 0);
+
+        // jmp short -4 from address 0
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0xeb, 0xfa]).unwrap();
+        assert_eq!(emu.step(), Ok(None));
+        assert_eq!(emu.eip(), 0xfffffffc);
+        match emu.step() {
+            Err(EmulatorError::MemoryOutOfBounds { eip: 0xfffffffc, address: 0xfffffffc, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // call rel32 with the largest displacement
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0xe8, 0xff, 0xff, 0xff, 0x7f]).unwrap();
+        assert_eq!(emu.step(), Ok(None));
+        assert_eq!(emu.eip(), 0x80000004);
+        assert_eq!(emu.memory_u32(emu.get_register(ESP)), Ok(5));
+    }
+
+    #[test]
+    fn random_code_never_panics() {
+        // xorshift, so failures are reproducible
+        let mut seed: u32 = 0x12345678;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            return seed;
+        };
+        for _ in 0..500 {
+            let mut emu = Emulator::new(TEST_MEMSIZE);
+            emu.set_stdin(Box::new(io::empty()));
+            emu.set_stdout(Box::new(io::sink()));
+            emu.set_stderr(Box::new(io::sink()));
+            let code: Vec<u8> = (0..64).map(|_| random() as u8).collect();
+            emu.load(0, &code).unwrap();
+            for reg in [EAX, ECX, EDX, EBX, ESP, EBP, ESI, EDI].iter() {
+                emu.set_register(*reg, if random() & 1 == 0 { random() } else { random() % TEST_MEMSIZE });
+            }
+            emu.set_step_limit(Some(1000));
+            let _ = emu.launch();
+        }
+        // fewer bytes than dump_memory shows
+        Emulator::new(16).dump_memory();
+    }
+
+    #[test]
+    fn launch_watchpoint() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // mov eax,[0x100]; mov [0x102],al; ret
+        emu.load(0, &[0x8b, 0x05, 0x00, 0x01, 0x00, 0x00, 0x88, 0x05, 0x02, 0x01, 0x00, 0x00, 0xc3]).unwrap();
+        let write = Watchpoint { address: 0x102, length: 1, kind: WatchKind::Write };
+        let read = Watchpoint { address: 0x103, length: 1, kind: WatchKind::Read };
+        emu.add_watchpoint(write);
+        emu.add_watchpoint(read);
+        assert_eq!(emu.launch(), Ok(StopReason::Watchpoint(read)));
+        assert_eq!(emu.eip(), 6);
+        assert_eq!(emu.launch(), Ok(StopReason::Watchpoint(write)));
+        assert_eq!(emu.eip(), 12);
+        emu.remove_watchpoint(write);
+        assert_eq!(emu.watchpoints(), &[read]);
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(0)));
+    }
+
     #[test]
     fn cmp_u32_u32() {
         let mut emu = Emulator::new(TEST_MEMSIZE);
@@ -1029,6 +2028,679 @@ mod tests {
         assert_eq!(emu.is_overflow(), true);
     }
 
+    fn run(code: &[u8]) -> Emulator {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, code).unwrap();
+        assert!(matches!(emu.launch(), Ok(StopReason::Exit(_))));
+        return emu;
+    }
+
+    #[test]
+    fn add_flags() {
+        // mov eax,0xffffffff; add eax,1; ret
+        let emu = run(&[0xb8, 0xff, 0xff, 0xff, 0xff, 0x05, 0x01, 0x00, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0);
+        assert_eq!(emu.is_zero(), true);
+        assert_eq!(emu.is_carry(), true);
+        assert_eq!(emu.is_auxiliary_carry(), true);
+        assert_eq!(emu.is_overflow(), false);
+    }
+
+    #[test]
+    fn add_rm32_imm8_overflow() {
+        // mov ebx,0x7fffffff; add ebx,1; ret
+        let emu = run(&[0xbb, 0xff, 0xff, 0xff, 0x7f, 0x83, 0xc3, 0x01, 0xc3]);
+        assert_eq!(emu.get_register(EBX), 0x80000000);
+        assert_eq!(emu.is_sign_flag(), true);
+        assert_eq!(emu.is_overflow(), true);
+        assert_eq!(emu.is_carry(), false);
+    }
+
+    #[test]
+    fn sub_loop() {
+        // mov ecx,5; mov eax,0; loop: add eax,2; sub ecx,1; jnz loop; ret
+        let emu = run(&[0xb9, 0x05, 0x00, 0x00, 0x00, 0xb8, 0x00, 0x00, 0x00, 0x00,
+                        0x83, 0xc0, 0x02, 0x83, 0xe9, 0x01, 0x75, 0xf8, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 10);
+        assert_eq!(emu.get_register(ECX), 0);
+        assert_eq!(emu.is_zero(), true);
+    }
+
+    #[test]
+    fn sub_borrow() {
+        // mov eax,1; sub eax,2 (81 /5); ret
+        let emu = run(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0x81, 0xe8, 0x02, 0x00, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0xffffffff);
+        assert_eq!(emu.is_carry(), true);
+        assert_eq!(emu.is_sign_flag(), true);
+        assert_eq!(emu.is_parity(), true);
+    }
+
+    #[test]
+    fn xor_zero() {
+        // mov eax,1; xor eax,eax; jz +5; mov eax,1; ret
+        let emu = run(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0x31, 0xc0, 0x74, 0x05,
+                        0xb8, 0x01, 0x00, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0);
+        assert_eq!(emu.is_zero(), true);
+        assert_eq!(emu.is_parity(), true);
+    }
+
+    #[test]
+    fn and_clears_carry() {
+        // mov eax,0; sub eax,1; and eax,0x0f; ret
+        let emu = run(&[0xb8, 0x00, 0x00, 0x00, 0x00, 0x83, 0xe8, 0x01, 0x83, 0xe0, 0x0f, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x0f);
+        assert_eq!(emu.is_carry(), false);
+        assert_eq!(emu.is_overflow(), false);
+        assert_eq!(emu.is_zero(), false);
+        assert_eq!(emu.is_parity(), true);
+    }
+
+    #[test]
+    fn modrm_sib_scaled_index() {
+        // mov ebx,0x100; mov ecx,2; mov dword [ebx+ecx*4+8],0x1234;
+        // mov eax,[0x110]; ret
+        let emu = run(&[0xbb, 0x00, 0x01, 0x00, 0x00, 0xb9, 0x02, 0x00, 0x00, 0x00,
+                        0xc7, 0x44, 0x8b, 0x08, 0x34, 0x12, 0x00, 0x00,
+                        0x8b, 0x05, 0x10, 0x01, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.memory_u32(0x110), Ok(0x1234));
+        assert_eq!(emu.get_register(EAX), 0x1234);
+    }
+
+    #[test]
+    fn modrm_memory_operands() {
+        // mov ebx,0x200; mov eax,5; mov [ebx],eax; add [ebx],eax;
+        // xor [ebx+0x00000000],eax (mod=10); sub eax,[ebx]; ret
+        let emu = run(&[0xbb, 0x00, 0x02, 0x00, 0x00, 0xb8, 0x05, 0x00, 0x00, 0x00,
+                        0x89, 0x03, 0x01, 0x03, 0x31, 0x83, 0x00, 0x00, 0x00, 0x00,
+                        0x2b, 0x03, 0xc3]);
+        assert_eq!(emu.memory_u32(0x200), Ok(10 ^ 5));
+        assert_eq!(emu.get_register(EAX), 5u32.wrapping_sub(10 ^ 5));
+    }
+
+    #[test]
+    fn modrm_register_operands() {
+        // mov eax,3; mov ecx,eax (8b /r, mod=11); push ecx (ff /6); pop edx; ret
+        let emu = run(&[0xb8, 0x03, 0x00, 0x00, 0x00, 0x8b, 0xc8, 0xff, 0xf1, 0x5a, 0xc3]);
+        assert_eq!(emu.get_register(ECX), 3);
+        assert_eq!(emu.get_register(EDX), 3);
+    }
+
+    #[test]
+    fn register_from_str() {
+        assert_eq!("eax".parse(), Ok(EAX));
+        assert_eq!("EDI".parse(), Ok(EDI));
+        assert_eq!("eip".parse::<Register>(), Err("unknown register: eip".to_string()));
+    }
+
+    #[test]
+    fn register_views() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.set_register(EAX, 0x12345678);
+        assert_eq!(emu.get_register8(Register8::AL), 0x78);
+        assert_eq!(emu.get_register8(Register8::AH), 0x56);
+        assert_eq!(emu.get_register16(Register16::AX), 0x5678);
+        emu.set_register8(Register8::AH, 0xab);
+        emu.set_register8(Register8::BL, 0xcd);
+        emu.set_register16(Register16::SP, 0xbeef);
+        assert_eq!(emu.get_register(EAX), 0x1234ab78);
+        assert_eq!(emu.get_register(EBX), 0xcd);
+        assert_eq!(emu.get_register(ESP) & 0xffff, 0xbeef);
+    }
+
+    #[test]
+    fn mov_byte() {
+        // mov eax,0x11223344; mov ah,0xff; mov byte [0x200],ah; mov cl,[0x200];
+        // mov byte [0x201],0x7f; mov dl,al; ret
+        let emu = run(&[0xb8, 0x44, 0x33, 0x22, 0x11, 0xb4, 0xff,
+                        0x88, 0x25, 0x00, 0x02, 0x00, 0x00,
+                        0x8a, 0x0d, 0x00, 0x02, 0x00, 0x00,
+                        0xc6, 0x05, 0x01, 0x02, 0x00, 0x00, 0x7f,
+                        0x88, 0xc2, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x1122ff44);
+        assert_eq!(emu.get_register(ECX), 0xff);
+        assert_eq!(emu.get_register(EDX), 0x44);
+        assert_eq!(emu.memory_u8(0x200), Ok(0xff));
+        assert_eq!(emu.memory_u8(0x201), Ok(0x7f));
+    }
+
+    #[test]
+    fn alu_byte() {
+        // mov eax,0x1ff; add al,1; ret
+        let emu = run(&[0xb8, 0xff, 0x01, 0x00, 0x00, 0x04, 0x01, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x100);
+        assert_eq!(emu.is_carry(), true);
+        assert_eq!(emu.is_zero(), true);
+
+        // mov ebx,0x7f; add bl,1 (80 /0); ret
+        let emu = run(&[0xbb, 0x7f, 0x00, 0x00, 0x00, 0x80, 0xc3, 0x01, 0xc3]);
+        assert_eq!(emu.get_register(EBX), 0x80);
+        assert_eq!(emu.is_overflow(), true);
+        assert_eq!(emu.is_sign_flag(), true);
+
+        // mov ecx,0x0100; cmp ch,1; ret
+        let emu = run(&[0xb9, 0x00, 0x01, 0x00, 0x00, 0x80, 0xfd, 0x01, 0xc3]);
+        assert_eq!(emu.get_register(ECX), 0x100);
+        assert_eq!(emu.is_zero(), true);
+    }
+
+    #[test]
+    fn operand_size_prefix() {
+        // mov eax,0x1234ffff; add ax,1; mov bx,0x5678; mov [0x200],bx;
+        // add word [0x200],-1 (66 83 /0); ret
+        let emu = run(&[0xb8, 0xff, 0xff, 0x34, 0x12, 0x66, 0x05, 0x01, 0x00,
+                        0x66, 0xbb, 0x78, 0x56,
+                        0x66, 0x89, 0x1d, 0x00, 0x02, 0x00, 0x00,
+                        0x66, 0x83, 0x05, 0x00, 0x02, 0x00, 0x00, 0xff, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x12340000);
+        assert_eq!(emu.get_register(EBX), 0x5678);
+        assert_eq!(emu.memory_u32(0x200), Ok(0x5677));
+        assert_eq!(emu.is_carry(), true);
+    }
+
+    #[test]
+    fn test_flags() {
+        // mov eax,0x80; test al,0x80; ret
+        let emu = run(&[0xb8, 0x80, 0x00, 0x00, 0x00, 0xa8, 0x80, 0xc3]);
+        assert_eq!(emu.is_zero(), false);
+        assert_eq!(emu.is_sign_flag(), true);
+
+        // mov eax,1; mov ecx,2; test ecx,eax; ret
+        let emu = run(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0xb9, 0x02, 0x00, 0x00, 0x00,
+                        0x85, 0xc1, 0xc3]);
+        assert_eq!(emu.is_zero(), true);
+        assert_eq!(emu.is_carry(), false);
+    }
+
+    #[test]
+    fn mul_imul() {
+        // mov eax,7; mov ecx,6; mul ecx; imul eax,eax,-3; imul eax,ecx; imul edx,ecx,0x1000; ret
+        let emu = run(&[0xb8, 0x07, 0x00, 0x00, 0x00, 0xb9, 0x06, 0x00, 0x00, 0x00, 0xf7, 0xe1,
+                        0x6b, 0xc0, 0xfd, 0x0f, 0xaf, 0xc1, 0x69, 0xd1, 0x00, 0x10, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.get_register(EAX), -756i32 as u32);
+        assert_eq!(emu.get_register(EDX), 0x6000);
+        assert_eq!(emu.is_carry(), false);
+        assert_eq!(emu.is_overflow(), false);
+
+        // mov eax,0x80000000; mov ecx,4; mul ecx; ret
+        let emu = run(&[0xb8, 0x00, 0x00, 0x00, 0x80, 0xb9, 0x04, 0x00, 0x00, 0x00, 0xf7, 0xe1, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0);
+        assert_eq!(emu.get_register(EDX), 2);
+        assert_eq!(emu.is_carry(), true);
+        assert_eq!(emu.is_overflow(), true);
+
+        // mov eax,0x8000; mov ecx,4; mul cx; ret
+        let emu = run(&[0xb8, 0x00, 0x80, 0x00, 0x00, 0xb9, 0x04, 0x00, 0x00, 0x00, 0x66, 0xf7, 0xe1, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0);
+        assert_eq!(emu.get_register(EDX), 2);
+
+        // mov eax,0x40000000; imul eax,eax,2 (imul overflows into the sign bit); ret
+        let emu = run(&[0xb8, 0x00, 0x00, 0x00, 0x40, 0x6b, 0xc0, 0x02, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x80000000);
+        assert_eq!(emu.is_overflow(), true);
+    }
+
+    #[test]
+    fn div_idiv_cdq() {
+        // mov eax,-100; cdq; mov ecx,-7; idiv ecx; ret
+        let emu = run(&[0xb8, 0x9c, 0xff, 0xff, 0xff, 0x99, 0xb9, 0xf9, 0xff, 0xff, 0xff, 0xf7, 0xf9, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 14);
+        assert_eq!(emu.get_register(EDX), -2i32 as u32);
+
+        // mov eax,0x123; mov cl,0x10; div cl; ret
+        let emu = run(&[0xb8, 0x23, 0x01, 0x00, 0x00, 0xb1, 0x10, 0xf6, 0xf1, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x0312);
+
+        // mov edx,1; mov eax,0; mov ecx,2; div ecx; ret
+        let emu = run(&[0xba, 0x01, 0x00, 0x00, 0x00, 0xb8, 0x00, 0x00, 0x00, 0x00,
+                        0xb9, 0x02, 0x00, 0x00, 0x00, 0xf7, 0xf1, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x80000000);
+        assert_eq!(emu.get_register(EDX), 0);
+
+        // mov eax,0x7fff; cwd; mov eax,0xffff8000; cwd; ret
+        let emu = run(&[0xb8, 0xff, 0x7f, 0x00, 0x00, 0x66, 0x99, 0xb8, 0x00, 0x80, 0xff, 0xff, 0x66, 0x99, 0xc3]);
+        assert_eq!(emu.get_register(EDX), 0xffff);
+    }
+
+    #[test]
+    fn divide_error() {
+        // xor edx,edx; xor ecx,ecx; div ecx
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0x31, 0xd2, 0x31, 0xc9, 0xf7, 0xf1]).unwrap();
+        assert_eq!(emu.launch(), Err(EmulatorError::DivideError { eip: 4, bytes: vec![0xf7, 0xf1] }));
+        assert_eq!(emu.eip(), 4);
+
+        // mov edx,1; mov ecx,1; div ecx (the quotient does not fit)
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0xba, 0x01, 0x00, 0x00, 0x00, 0xb9, 0x01, 0x00, 0x00, 0x00, 0xf7, 0xf1]).unwrap();
+        assert!(matches!(emu.launch(), Err(EmulatorError::DivideError { eip: 10, .. })));
+
+        // mov eax,0x80000000; cdq; mov ecx,-1; idiv ecx
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0xb8, 0x00, 0x00, 0x00, 0x80, 0x99, 0xb9, 0xff, 0xff, 0xff, 0xff, 0xf7, 0xf9]).unwrap();
+        assert!(matches!(emu.launch(), Err(EmulatorError::DivideError { eip: 11, .. })));
+        assert_eq!(emu.get_register(EAX), 0x80000000);
+    }
+
+    #[test]
+    fn not_neg_test() {
+        // mov ebx,0xf0; not ebx; neg ebx; test bl,1; ret
+        let emu = run(&[0xbb, 0xf0, 0x00, 0x00, 0x00, 0xf7, 0xd3, 0xf7, 0xdb, 0xf6, 0xc3, 0x01, 0xc3]);
+        assert_eq!(emu.get_register(EBX), 0xf1);
+        assert_eq!(emu.is_zero(), false);
+        assert_eq!(emu.is_carry(), false);
+
+        // mov ebx,0xf0; neg bl; ret
+        let emu = run(&[0xbb, 0xf0, 0x00, 0x00, 0x00, 0xf6, 0xdb, 0xc3]);
+        assert_eq!(emu.get_register(EBX), 0x10);
+        assert_eq!(emu.is_carry(), true);
+    }
+
+    #[test]
+    fn shift_group() {
+        // mov eax,0x81; mov ecx,4; shl eax,cl; rol al,1; sar eax,2; ret
+        let emu = run(&[0xb8, 0x81, 0x00, 0x00, 0x00, 0xb9, 0x04, 0x00, 0x00, 0x00, 0xd3, 0xe0,
+                        0xd0, 0xc0, 0xc1, 0xf8, 0x02, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x208);
+        assert_eq!(emu.is_carry(), false);
+
+        // mov dword [0x100],0x80000000; shr dword [0x100],31; sar byte [0x100],1 (C0 form); ret
+        let emu = run(&[0xc7, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+                        0xc1, 0x2d, 0x00, 0x01, 0x00, 0x00, 0x1f, 0xc0, 0x3d, 0x00, 0x01, 0x00, 0x00, 0x01, 0xc3]);
+        assert_eq!(emu.memory_u32(0x100), Ok(0));
+        assert_eq!(emu.is_carry(), true);
+
+        // mov eax,0x80; rcl al,1 (bit 7 into CF); rcl al,1 (CF back into bit 0); ret
+        let emu = run(&[0xb8, 0x80, 0x00, 0x00, 0x00, 0xd0, 0xd0, 0xd0, 0xd0, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x01);
+        assert_eq!(emu.is_carry(), false);
+
+        // mov eax,1; mov ecx,0x20; shl eax,cl (masked to 0: nothing changes); ret
+        let emu = run(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0xb9, 0x20, 0x00, 0x00, 0x00, 0xd3, 0xe0, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 1);
+    }
+
+    #[test]
+    fn shld_shrd() {
+        // mov ebx,0x12345678; mov edx,0x9abcdef0; mov ecx,4; shld ebx,edx,8; shrd edx,ebx,cl; rcr ebx,1; ret
+        let emu = run(&[0xbb, 0x78, 0x56, 0x34, 0x12, 0xba, 0xf0, 0xde, 0xbc, 0x9a, 0xb9, 0x04, 0x00, 0x00, 0x00,
+                        0x0f, 0xa4, 0xd3, 0x08, 0x0f, 0xad, 0xda, 0xd1, 0xdb, 0xc3]);
+        assert_eq!(emu.get_register(EDX), 0xa9abcdef);
+        assert_eq!(emu.get_register(EBX), 0x1a2b3c4d);
+        assert_eq!(emu.is_carry(), false);
+    }
+
+    fn run_with_data(code: &[u8], data: &[u8]) -> Emulator {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, code).unwrap();
+        emu.load(0x100, data).unwrap();
+        assert!(matches!(emu.launch(), Ok(StopReason::Exit(_))));
+        return emu;
+    }
+
+    #[test]
+    fn string_instructions() {
+        // mov esi,0x100; mov edi,0x200; mov ecx,3; rep movsd;
+        // mov edi,0x300; mov al,'a'; mov ecx,5; rep stosb; ret
+        let emu = run_with_data(&[0xbe, 0x00, 0x01, 0x00, 0x00, 0xbf, 0x00, 0x02, 0x00, 0x00,
+                                  0xb9, 0x03, 0x00, 0x00, 0x00, 0xf3, 0xa5, 0xbf, 0x00, 0x03, 0x00, 0x00,
+                                  0xb0, 0x61, 0xb9, 0x05, 0x00, 0x00, 0x00, 0xf3, 0xaa, 0xc3], b"remu386 test");
+        assert_eq!(emu.memory_slice(0x200, 12), Ok(&b"remu386 test"[..]));
+        assert_eq!(emu.memory_slice(0x300, 6), Ok(&b"aaaaa\0"[..]));
+        assert_eq!(emu.get_register(ESI), 0x10c);
+        assert_eq!(emu.get_register(EDI), 0x305);
+        assert_eq!(emu.get_register(ECX), 0);
+
+        // strlen: mov edi,0x100; xor eax,eax; mov ecx,-1; repne scasb; ret
+        let emu = run_with_data(&[0xbf, 0x00, 0x01, 0x00, 0x00, 0x31, 0xc0, 0xb9, 0xff, 0xff, 0xff, 0xff,
+                                  0xf2, 0xae, 0xc3], b"remu386\0");
+        assert_eq!(!emu.get_register(ECX) - 1, 7);
+        assert_eq!(emu.get_register(EDI), 0x108);
+        assert_eq!(emu.is_zero(), true);
+
+        // memcmp: mov esi,0x100; mov edi,0x200; mov ecx,12; repe cmpsb; ret
+        let mut code = vec![0xbe, 0x00, 0x01, 0x00, 0x00, 0xbf, 0x00, 0x02, 0x00, 0x00,
+                            0xb9, 0x0c, 0x00, 0x00, 0x00, 0xf3, 0xa6, 0xc3];
+        code.resize(0x100, 0);
+        code.extend_from_slice(b"remu386 test");
+        let emu = run_with_data(&code, b"");
+        assert_eq!(emu.get_register(ECX), 11);
+        code.resize(0x200, 0);
+        code.extend_from_slice(b"remu486 test");
+        let emu = run_with_data(&code, b"");
+        assert_eq!(emu.get_register(ECX), 7);
+        assert_eq!(emu.get_register(ESI), 0x105);
+        assert_eq!(emu.is_zero(), false);
+        assert_eq!(emu.is_carry(), true);
+
+        // std; mov esi,0x10b; lodsw; ret
+        let emu = run_with_data(&[0xfd, 0xbe, 0x0b, 0x01, 0x00, 0x00, 0x66, 0xad, 0xc3], b"remu386 test");
+        assert_eq!(emu.get_register(EAX), 0x74);
+        assert_eq!(emu.get_register(ESI), 0x109);
+        assert_eq!(emu.is_direction(), true);
+    }
+
+    #[test]
+    fn rep_interruptible() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // mov ecx,100; mov edi,0x100; rep stosb; ret
+        emu.load(0, &[0xb9, 0x64, 0x00, 0x00, 0x00, 0xbf, 0x00, 0x01, 0x00, 0x00, 0xf3, 0xaa, 0xc3]).unwrap();
+        emu.load(0x100, &[0xff; 100]).unwrap();
+        emu.set_step_limit(Some(12));
+        assert_eq!(emu.launch(), Ok(StopReason::StepLimit));
+        assert_eq!(emu.eip(), 10);
+        assert_eq!(emu.get_register(ECX), 90);
+        assert_eq!(emu.memory_u8(0x109), Ok(0));
+        assert_eq!(emu.memory_u8(0x10a), Ok(0xff));
+
+        // a breakpoint on the instruction does not fire between iterations
+        emu.set_step_limit(None);
+        emu.add_breakpoint(10);
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(0)));
+        assert_eq!(emu.get_register(ECX), 0);
+        assert_eq!(emu.memory_slice(0x100, 100), Ok(&[0; 100][..]));
+
+        // with ECX = 0 nothing is stored
+        emu.set_eip(10);
+        emu.push32(0).unwrap();
+        emu.set_register(EDI, 0x200);
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(0)));
+        assert_eq!(emu.get_register(EDI), 0x200);
+    }
+
+    #[test]
+    fn alu_matrix() {
+        // mov dword [0x100],0xffffffff; mov dword [0x104],1; mov eax,1; xor edx,edx;
+        // add [0x100],eax; adc [0x104],edx (a 64-bit increment);
+        // mov ebx,0; sub ebx,1; sbb ecx,ecx; or ecx,[0x104];
+        // or byte [0x108],0x81; xor [0x108],cl; and word [0x108],0x00f0;
+        // cmp byte [0x104],2; test [0x104],ecx; ret
+        let emu = run(&[0xc7, 0x05, 0x00, 0x01, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff,
+                        0xc7, 0x05, 0x04, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+                        0xb8, 0x01, 0x00, 0x00, 0x00, 0x31, 0xd2,
+                        0x01, 0x05, 0x00, 0x01, 0x00, 0x00, 0x11, 0x15, 0x04, 0x01, 0x00, 0x00,
+                        0xbb, 0x00, 0x00, 0x00, 0x00, 0x83, 0xeb, 0x01, 0x19, 0xc9,
+                        0x0b, 0x0d, 0x04, 0x01, 0x00, 0x00,
+                        0x80, 0x0d, 0x08, 0x01, 0x00, 0x00, 0x81, 0x30, 0x0d, 0x08, 0x01, 0x00, 0x00,
+                        0x66, 0x81, 0x25, 0x08, 0x01, 0x00, 0x00, 0xf0, 0x00,
+                        0x80, 0x3d, 0x04, 0x01, 0x00, 0x00, 0x02, 0x85, 0x0d, 0x04, 0x01, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.memory_u32(0x100), Ok(0));
+        assert_eq!(emu.memory_u32(0x104), Ok(2));
+        assert_eq!(emu.get_register(EBX), 0xffffffff);
+        assert_eq!(emu.get_register(ECX), 0xffffffff);
+        assert_eq!(emu.memory_u32(0x108), Ok(0x70));
+        assert_eq!(emu.is_zero(), false);
+        assert_eq!(emu.is_carry(), false);
+
+        // mov al,0; sub al,1 (CF=1); adc al,0x10 (CF=1); sbb al,0x10; ret
+        let emu = run(&[0xb0, 0x00, 0x2c, 0x01, 0x14, 0x10, 0x1c, 0x10, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0xff);
+        assert_eq!(emu.is_carry(), true);
+
+        // every op in the 00-3D block, 80 and 82 forms decode and run
+        for op in 0..8u8 {
+            let emu = run(&[0xb8, 0x0f, 0x00, 0x00, 0x00, op << 3 | 0x04, 0x03, 0x82, 0xc0 | op << 3, 0x01, 0xc3]);
+            let expected = [0x13, 0x0f, 0x13, 0x0b, 0x01, 0x0b, 0x0d, 0x0f][op as usize];
+            assert_eq!(emu.get_register(EAX), expected, "{}", ALU_NAME[op as usize]);
+        }
+    }
+
+    #[test]
+    fn inc_dec() {
+        // mov eax,0x7fffffff; inc eax; mov ebx,0; sub ebx,1; dec ebx;
+        // inc byte [0x100]; dec dword [0x104]; ret
+        let emu = run(&[0xb8, 0xff, 0xff, 0xff, 0x7f, 0x40, 0xbb, 0x00, 0x00, 0x00, 0x00, 0x83, 0xeb, 0x01, 0x4b,
+                        0xfe, 0x05, 0x00, 0x01, 0x00, 0x00, 0xff, 0x0d, 0x04, 0x01, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x80000000);
+        assert_eq!(emu.get_register(EBX), 0xfffffffe);
+        assert_eq!(emu.memory_u8(0x100), Ok(1));
+        assert_eq!(emu.memory_u32(0x104), Ok(0xffffffff));
+        // the carry from the SUB survives both DECs
+        assert_eq!(emu.is_carry(), true);
+        assert_eq!(emu.is_sign_flag(), true);
+
+        // mov eax,0x1ffff; inc ax; ret
+        let emu = run(&[0xb8, 0xff, 0xff, 0x01, 0x00, 0x66, 0x40, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 0x10000);
+        assert_eq!(emu.is_zero(), true);
+    }
+
+    #[test]
+    fn push_pop_variants() {
+        // push -2; push 0x12345678; pop dword [0x108]; pop ecx;
+        // push 5; push 7; pop dword [esp]; pop eax; ret
+        let emu = run(&[0x6a, 0xfe, 0x68, 0x78, 0x56, 0x34, 0x12, 0x8f, 0x05, 0x08, 0x01, 0x00, 0x00, 0x59,
+                        0x6a, 0x05, 0x6a, 0x07, 0x8f, 0x04, 0x24, 0x58, 0xc3]);
+        assert_eq!(emu.get_register(ECX), 0xfffffffe);
+        assert_eq!(emu.memory_u32(0x108), Ok(0x12345678));
+        assert_eq!(emu.get_register(EAX), 7);
+        assert_eq!(emu.get_register(ESP), TEST_MEMSIZE);
+
+        // push ax; pop bx; ret
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0x66, 0x50, 0x66, 0x5b, 0xc3]).unwrap();
+        emu.set_register(EAX, 0x1234abcd);
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(0x1234abcd)));
+        assert_eq!(emu.get_register(EBX), 0xabcd);
+    }
+
+    #[test]
+    fn pushad_popad() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // pushad; hlt; (xor eax,eax; xor ebp,ebp; popad; ret)
+        emu.load(0, &[0x60, 0xf4, 0x31, 0xc0, 0x31, 0xed, 0x61, 0xc3]).unwrap();
+        for (i, reg) in [EAX, ECX, EDX, EBX, EBP, ESI, EDI].iter().enumerate() {
+            emu.set_register(*reg, 0x10 + i as u32);
+        }
+        let esp = emu.get_register(ESP);
+        assert_eq!(emu.launch(), Ok(StopReason::Halt));
+        assert_eq!(emu.get_register(ESP), esp - 32);
+        assert_eq!(emu.memory_u32(esp - 4), Ok(0x10));
+        assert_eq!(emu.memory_u32(esp - 20), Ok(esp));
+        assert_eq!(emu.memory_u32(esp - 32), Ok(0x16));
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(0x10)));
+        assert_eq!(emu.get_register(EBP), 0x14);
+        assert_eq!(emu.get_register(EDI), 0x16);
+        assert_eq!(emu.get_register(ESP), esp + 4);
+    }
+
+    #[test]
+    fn pushfd_popfd() {
+        // mov eax,0; sub eax,1; pushfd; pop edx; push -1; popfd; pushfd; pop esi; ret
+        let emu = run(&[0xb8, 0x00, 0x00, 0x00, 0x00, 0x83, 0xe8, 0x01, 0x9c, 0x5a,
+                        0x6a, 0xff, 0x9d, 0x9c, 0x5e, 0xc3]);
+        assert_eq!(emu.get_register(EDX), CF | PF | AF | SF | 0x2);
+        assert_eq!(emu.get_register(ESI), 0x244cd7);
+        assert_eq!(emu.is_direction(), true);
+    }
+
+    #[test]
+    fn enter_leave() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // enter 8,0; enter 4,2; hlt; leave; leave; ret
+        emu.load(0, &[0xc8, 0x08, 0x00, 0x00, 0xc8, 0x04, 0x00, 0x02, 0xf4, 0xc9, 0xc9, 0xc3]).unwrap();
+        let esp = emu.get_register(ESP);
+        assert_eq!(emu.launch(), Ok(StopReason::Halt));
+        assert_eq!(emu.get_register(EBP), esp - 16);
+        assert_eq!(emu.get_register(ESP), esp - 28);
+        // the saved EBP, the outer frame pointer copied from the display, and the new frame pointer
+        assert_eq!(emu.memory_u32(esp - 16), Ok(esp - 4));
+        assert_eq!(emu.memory_u32(esp - 20), Ok(0));
+        assert_eq!(emu.memory_u32(esp - 24), Ok(esp - 16));
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(0)));
+        assert_eq!(emu.get_register(EBP), 0);
+        assert_eq!(emu.get_register(ESP), esp + 4);
+    }
+
+    #[test]
+    fn indirect_call_jump() {
+        // push 3; push 4; mov eax,add2; call eax; mov ecx,2; jmp [table+ecx*4]
+        // add2: mov eax,[esp+4]; add eax,[esp+8]; ret 8
+        // one: mov ebx,1; ret
+        // two: mov ebx,2; jmp short done
+        // done: call [fptr]; ret
+        // addone: inc ebx; ret
+        // table: dd add2, add2, two; fptr: dd addone
+        let emu = run(&[0x6a, 0x03, 0x6a, 0x04, 0xb8, 0x17, 0x00, 0x00, 0x00, 0xff, 0xd0, 0xb9, 0x02, 0x00,
+                        0x00, 0x00, 0xff, 0x24, 0x8d, 0x38, 0x00, 0x00, 0x00, 0x8b, 0x44, 0x24, 0x04, 0x03,
+                        0x44, 0x24, 0x08, 0xc2, 0x08, 0x00, 0xbb, 0x01, 0x00, 0x00, 0x00, 0xc3, 0xbb, 0x02,
+                        0x00, 0x00, 0x00, 0xeb, 0x00, 0xff, 0x15, 0x44, 0x00, 0x00, 0x00, 0xc3, 0x43, 0xc3,
+                        0x22, 0x00, 0x00, 0x00, 0x22, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x36, 0x00,
+                        0x00, 0x00]);
+        assert_eq!(emu.get_register(EAX), 7);
+        assert_eq!(emu.get_register(EBX), 3);
+        assert_eq!(emu.get_register(ESP), Emulator::new(TEST_MEMSIZE).get_register(ESP) + 4);
+        assert_eq!(emu.eip(), 0x36);
+    }
+
+    #[test]
+    fn loop_jecxz() {
+        // mov ecx,5; xor eax,eax; l1: add eax,ecx; loop l1; jecxz z; mov eax,99
+        // z: mov ecx,10; xor edx,edx; l2: inc edx; cmp edx,3; loopne l2; mov esi,ecx
+        // mov ecx,4; l3: cmp eax,eax; loope l3; jmp far; times 0x80 hlt; far: ret
+        let mut code = vec![0xb9, 0x05, 0x00, 0x00, 0x00, 0x31, 0xc0, 0x01, 0xc8, 0xe2, 0xfc, 0xe3, 0x05, 0xb8,
+                            0x63, 0x00, 0x00, 0x00, 0xb9, 0x0a, 0x00, 0x00, 0x00, 0x31, 0xd2, 0x42, 0x83, 0xfa,
+                            0x03, 0xe0, 0xfa, 0x89, 0xce, 0xb9, 0x04, 0x00, 0x00, 0x00, 0x39, 0xc0, 0xe1, 0xfc,
+                            0xe9, 0x80, 0x00, 0x00, 0x00];
+        code.extend_from_slice(&[0xf4; 0x80]);
+        code.push(0xc3);
+        let emu = run(&code);
+        assert_eq!(emu.get_register(EAX), 15);
+        assert_eq!(emu.get_register(EDX), 3);
+        assert_eq!(emu.get_register(ESI), 7);
+        assert_eq!(emu.get_register(ECX), 0);
+        // a taken LOOP leaves the flags alone
+        assert_eq!(emu.is_zero(), true);
+    }
+
+    #[test]
+    fn far_transfers_unsupported() {
+        // call far [0x100]; jmp far [0x100]; retf; call far 0x8:0x0
+        for code in [&[0xff, 0x1d, 0x00, 0x01, 0x00, 0x00][..], &[0xff, 0x2d, 0x00, 0x01, 0x00, 0x00],
+                     &[0xcb], &[0x9a, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00]].iter() {
+            let mut emu = Emulator::new(TEST_MEMSIZE);
+            emu.load(0, code).unwrap();
+            assert!(matches!(emu.launch(), Err(EmulatorError::UnknownOpcode { eip: 0, .. })));
+        }
+    }
+
+    #[test]
+    fn movzx_movsx() {
+        // mov eax,0x12345680; movzx ecx,al; movsx edx,al; mov esi,0xaaaaaaaa; movsx si,al;
+        // movzx edi,word [0x104]; movsx ebp,word [0x104]; ret
+        let emu = run_with_data(&[0xb8, 0x80, 0x56, 0x34, 0x12, 0x0f, 0xb6, 0xc8, 0x0f, 0xbe, 0xd0, 0xbe, 0xaa,
+                                  0xaa, 0xaa, 0xaa, 0x66, 0x0f, 0xbe, 0xf0, 0x0f, 0xb7, 0x3d, 0x04, 0x01, 0x00,
+                                  0x00, 0x0f, 0xbf, 0x2d, 0x04, 0x01, 0x00, 0x00, 0xc3],
+                                &[0x10, 0x20, 0x30, 0x40, 0xfe, 0xff]);
+        assert_eq!(emu.get_register(ECX), 0x80);
+        assert_eq!(emu.get_register(EDX), 0xffffff80);
+        assert_eq!(emu.get_register(ESI), 0xaaaaff80);
+        assert_eq!(emu.get_register(EDI), 0xfffe);
+        assert_eq!(emu.get_register(EBP), 0xfffffffe);
+    }
+
+    #[test]
+    fn xchg_moffs_cbw_xlat() {
+        // mov eax,0x11223344; mov ecx,0x55667788; xchg ecx,eax; xchg [0x100],ecx; xchg ch,cl;
+        // mov [0x108],eax; mov ax,[0x104]; mov al,[0x101]; mov [0x10c],al; xchg dx,ax;
+        // mov al,0x85; cbw; mov edi,eax; cwde; mov ebx,0x100; mov al,4; xlatb; ret
+        let emu = run_with_data(&[0xb8, 0x44, 0x33, 0x22, 0x11, 0xb9, 0x88, 0x77, 0x66, 0x55, 0x91, 0x87, 0x0d,
+                                  0x00, 0x01, 0x00, 0x00, 0x86, 0xcd, 0xa3, 0x08, 0x01, 0x00, 0x00, 0x66, 0xa1,
+                                  0x04, 0x01, 0x00, 0x00, 0xa0, 0x01, 0x01, 0x00, 0x00, 0xa2, 0x0c, 0x01, 0x00,
+                                  0x00, 0x66, 0x92, 0xb0, 0x85, 0x66, 0x98, 0x89, 0xc7, 0x98, 0xbb, 0x00, 0x01,
+                                  0x00, 0x00, 0xb0, 0x04, 0xd7, 0xc3],
+                                &[0x10, 0x20, 0x30, 0x40, 0xfe, 0xff]);
+        assert_eq!(emu.memory_u32(0x100), Ok(0x11223344));
+        assert_eq!(emu.get_register(ECX), 0x40301020);
+        assert_eq!(emu.memory_u32(0x108), Ok(0x55667788));
+        assert_eq!(emu.memory_u8(0x10c), Ok(0x33));
+        assert_eq!(emu.get_register(EDX), 0xff33);
+        assert_eq!(emu.get_register(EDI), 0x5566ff85);
+        assert_eq!(emu.get_register(EAX), 0xfffffffe);
+    }
+
+    #[test]
+    fn bit_test_scan() {
+        // mov ebx,0x104; mov ecx,-1; bts [ebx],ecx; setc al; mov ecx,40; bt [ebx],ecx; setc ah;
+        // btr dword [ebx+4],8; setc dl; mov esi,0x80000001; btc esi,33; mov edi,35; bts esi,edi;
+        // bsf ecx,esi; bsr ebp,esi; bsr cx,si; mov edi,0x1234; bsf edi,[0x10c]; ret
+        let emu = run_with_data(&[0xbb, 0x04, 0x01, 0x00, 0x00, 0xb9, 0xff, 0xff, 0xff, 0xff, 0x0f, 0xab, 0x0b,
+                                  0x0f, 0x92, 0xc0, 0xb9, 0x28, 0x00, 0x00, 0x00, 0x0f, 0xa3, 0x0b, 0x0f, 0x92,
+                                  0xc4, 0x0f, 0xba, 0x73, 0x04, 0x08, 0x0f, 0x92, 0xc2, 0xbe, 0x01, 0x00, 0x00,
+                                  0x80, 0x0f, 0xba, 0xfe, 0x21, 0xbf, 0x23, 0x00, 0x00, 0x00, 0x0f, 0xab, 0xfe,
+                                  0x0f, 0xbc, 0xce, 0x0f, 0xbd, 0xee, 0x66, 0x0f, 0xbd, 0xce, 0xbf, 0x34, 0x12,
+                                  0x00, 0x00, 0x0f, 0xbc, 0x3d, 0x0c, 0x01, 0x00, 0x00, 0xc3],
+                                &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+        // a negative offset reaches the dword below the operand, 40 the one above
+        assert_eq!(emu.memory_u8(0x103), Ok(0x80));
+        assert_eq!(emu.memory_u8(0x109), Ok(0));
+        assert_eq!(emu.get_register(EAX), 0x100);
+        assert_eq!(emu.get_register(EDX), 1);
+        // register offsets wrap within the register
+        assert_eq!(emu.get_register(ESI), 0x8000000b);
+        assert_eq!(emu.get_register(ECX), 3);
+        assert_eq!(emu.get_register(EBP), 31);
+        // a zero source leaves the destination unchanged
+        assert_eq!(emu.get_register(EDI), 0x1234);
+        assert_eq!(emu.is_zero(), true);
+
+        // 0F BA /0-/3 are undefined
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0x0f, 0xba, 0xc0, 0x00]).unwrap();
+        assert!(matches!(emu.launch(), Err(EmulatorError::UnknownOpcode { eip: 0, .. })));
+    }
+
+    #[test]
+    fn disassemble_memory() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0x10, &[0x83, 0xc3, 0x01, 0xc3]).unwrap();
+        let insn = emu.disassemble(0x10).unwrap();
+        assert_eq!(insn.text, "add EBX, 0x1");
+        assert_eq!(insn.length(), 3);
+        assert_eq!(emu.disassemble(0x13).unwrap().text, "ret");
+        // mov eax,imm32 cut off by the end of memory
+        emu.load(TEST_MEMSIZE - 2, &[0xb8, 0x01]).unwrap();
+        assert_eq!(emu.disassemble(TEST_MEMSIZE - 2), None);
+        assert_eq!(emu.disassemble(TEST_MEMSIZE), None);
+    }
+
+    #[test]
+    fn condition_codes() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        let cases: [(u32, &[&str]); 7] = [
+            (0, &["no", "ae", "nz", "a", "ns", "np", "ge", "g"]),
+            (CF, &["no", "b", "nz", "be", "ns", "np", "ge", "g"]),
+            (ZF | PF, &["no", "ae", "z", "be", "ns", "p", "ge", "le"]),
+            (SF, &["no", "ae", "nz", "a", "s", "np", "l", "le"]),
+            (OF, &["o", "ae", "nz", "a", "ns", "np", "l", "le"]),
+            (SF | OF, &["o", "ae", "nz", "a", "s", "np", "ge", "g"]),
+            (ZF | SF | OF, &["o", "ae", "z", "be", "s", "np", "ge", "le"]),
+        ];
+        for (eflags, conditions) in cases.iter() {
+            emu.set_eflags(*eflags);
+            for cc in 0..16 {
+                let name = CONDITION_NAME[cc as usize];
+                assert_eq!(emu.condition(cc), conditions.contains(&name), "{} with {:X}", name, eflags);
+            }
+        }
+    }
+
+    #[test]
+    fn jcc_setcc_cmovcc() {
+        // mov eax,1; cmp eax,2; setb cl; setg dl; mov ebx,5; cmovl ebx,eax;
+        // jb +5 (0f 82); mov eax,7; cmp eax,0; ja +5; mov ecx,9; ret
+        let emu = run(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0x83, 0xf8, 0x02,
+                        0x0f, 0x92, 0xc1, 0x0f, 0x9f, 0xc2,
+                        0xbb, 0x05, 0x00, 0x00, 0x00, 0x0f, 0x4c, 0xd8,
+                        0x0f, 0x82, 0x05, 0x00, 0x00, 0x00, 0xb8, 0x07, 0x00, 0x00, 0x00,
+                        0x83, 0xf8, 0x00, 0x77, 0x05, 0xb9, 0x09, 0x00, 0x00, 0x00, 0xc3]);
+        assert_eq!(emu.get_register(EAX), 1);
+        assert_eq!(emu.get_register(EBX), 1);
+        assert_eq!(emu.get_register(ECX), 1);
+        assert_eq!(emu.get_register(EDX), 0);
+    }
+
     #[test]
     fn eflags_carry() {
         let mut emu = Emulator::new(TEST_MEMSIZE);
@@ -0,0 +1,470 @@
+use std::fmt;
+
+use super::modrm::{ModRM, RM};
+use super::{sized_register_name, ALU_NAME, CONDITION_NAME};
+
+// longest valid i386 instruction
+pub const MAX_LENGTH: usize = 15;
+
+const SHIFT_NAME: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+const GROUP3_NAME: [&str; 8] = ["test", "test", "not", "neg", "mul", "imul", "div", "idiv"];
+const BIT_NAME: [&str; 4] = ["bt", "bts", "btr", "btc"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub text: String
+}
+
+impl Instruction {
+    pub fn length(&self) -> u32 {
+        return self.bytes.len() as u32;
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        return write!(f, "{:08X}: {:<24}{}", self.address, bytes.join(" "), self.text);
+    }
+}
+
+// Decodes the instruction at the start of `code`, which was loaded at `address`.
+// Returns None for unknown opcodes and truncated instructions.
+pub fn disassemble(code: &[u8], address: u32) -> Option<Instruction> {
+    let code = &code[..code.len().min(MAX_LENGTH)];
+    let mut decoder = Decoder {
+        code,
+        address,
+        pos: 0,
+        operand_size: 32
+    };
+    let text = decoder.instruction()?;
+    return Some(Instruction {
+        address,
+        bytes: code[..decoder.pos].to_vec(),
+        text
+    });
+}
+
+fn ptr_name(size: u32) -> &'static str {
+    return match size {
+        8 => "byte",
+        16 => "word",
+        48 => "fword",
+        _ => "dword"
+    };
+}
+
+fn rm_text(rm: RM, size: u32) -> String {
+    return match rm {
+        RM::Register(index) => sized_register_name(index, size).to_string(),
+        RM::Memory(address) => format!("{} ptr {}", ptr_name(size), address)
+    };
+}
+
+fn string_name(name: &str, size: u32) -> String {
+    let suffix = match size {
+        8 => "b",
+        16 => "w",
+        _ => "d"
+    };
+    return format!("{}{}", name, suffix);
+}
+
+struct Decoder<'a> {
+    code: &'a [u8],
+    address: u32,
+    pos: usize,
+    operand_size: u32
+}
+
+impl<'a> Decoder<'a> {
+    fn next(&mut self) -> Option<u8> {
+        let value = *self.code.get(self.pos)?;
+        self.pos += 1;
+        return Some(value);
+    }
+
+    fn imm(&mut self, size: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..size / 8 {
+            value |= (self.next()? as u32) << (8 * i);
+        }
+        return Some(value);
+    }
+
+    fn imm_text(&mut self, size: u32) -> Option<String> {
+        return Some(format!("{:#X}", self.imm(size)?));
+    }
+
+    // sign-extended imm8 shown at the operand size
+    fn simm8_text(&mut self, size: u32) -> Option<String> {
+        let value = self.next()? as i8 as i32 as u32;
+        return Some(format!("{:#X}", value & super::flags::mask(size)));
+    }
+
+    // relative operands are shown as the absolute target
+    fn rel_text(&mut self, size: u32) -> Option<String> {
+        let value = if size == 8 {
+            self.next()? as i8 as i32
+        } else {
+            self.imm(32)? as i32
+        };
+        let target = self.address.wrapping_add(self.pos as u32).wrapping_add(value as u32);
+        return Some(format!("{:#X}", target));
+    }
+
+    fn modrm(&mut self) -> Option<(ModRM, RM)> {
+        let modrm = ModRM::new(self.next()? as u32);
+        let rm = modrm.decode(|| self.next().ok_or(())).ok()?;
+        return Some((modrm, rm));
+    }
+
+    fn rm_r(&mut self, name: &str, size: u32) -> Option<String> {
+        let (modrm, rm) = self.modrm()?;
+        return Some(format!("{} {}, {}", name, rm_text(rm, size), sized_register_name(modrm.reg, size)));
+    }
+
+    fn r_rm(&mut self, name: &str, size: u32, rm_size: u32) -> Option<String> {
+        let (modrm, rm) = self.modrm()?;
+        return Some(format!("{} {}, {}", name, sized_register_name(modrm.reg, size), rm_text(rm, rm_size)));
+    }
+
+    fn acc_imm(&mut self, name: &str, size: u32) -> Option<String> {
+        let value = self.imm_text(size)?;
+        return Some(format!("{} {}, {}", name, sized_register_name(0, size), value));
+    }
+
+    fn instruction(&mut self) -> Option<String> {
+        let mut prefix = "";
+        let mut code = self.next()?;
+        loop {
+            if code == 0x66 {
+                self.operand_size = 16;
+            } else if code == 0xf3 {
+                prefix = "rep ";
+            } else if code == 0xf2 {
+                prefix = "repne ";
+            } else {
+                break;
+            }
+            code = self.next()?;
+        }
+        if prefix == "rep " && [0xa6, 0xa7, 0xae, 0xaf].contains(&code) {
+            prefix = "repe ";
+        }
+        let text = self.opcode(code as u32)?;
+        return Some(format!("{}{}", prefix, text));
+    }
+
+    fn opcode(&mut self, code: u32) -> Option<String> {
+        let size = self.operand_size;
+        if code < 0x40 && code & 0x07 < 6 {
+            let name = ALU_NAME[(code >> 3) as usize];
+            let form = code & 0x07;
+            return if form == 0 {
+                self.rm_r(name, 8)
+            } else if form == 1 {
+                self.rm_r(name, size)
+            } else if form == 2 {
+                self.r_rm(name, 8, 8)
+            } else if form == 3 {
+                self.r_rm(name, size, size)
+            } else if form == 4 {
+                self.acc_imm(name, 8)
+            } else {
+                self.acc_imm(name, size)
+            };
+        } else if code == 0x0f {
+            return self.opcode0f();
+        } else if (0x40..=0x47).contains(&code) {
+            return Some(format!("inc {}", sized_register_name(code - 0x40, size)));
+        } else if (0x48..=0x4f).contains(&code) {
+            return Some(format!("dec {}", sized_register_name(code - 0x48, size)));
+        } else if (0x50..=0x57).contains(&code) {
+            return Some(format!("push {}", sized_register_name(code - 0x50, size)));
+        } else if (0x58..=0x5f).contains(&code) {
+            return Some(format!("pop {}", sized_register_name(code - 0x58, size)));
+        } else if code == 0x60 {
+            return Some(if size == 16 { "pusha" } else { "pushad" }.to_string());
+        } else if code == 0x61 {
+            return Some(if size == 16 { "popa" } else { "popad" }.to_string());
+        } else if code == 0x68 {
+            return Some(format!("push {}", self.imm_text(size)?));
+        } else if code == 0x69 || code == 0x6b {
+            let (modrm, rm) = self.modrm()?;
+            let value = if code == 0x69 { self.imm_text(size)? } else { self.simm8_text(size)? };
+            return Some(format!("imul {}, {}, {}", sized_register_name(modrm.reg, size), rm_text(rm, size), value));
+        } else if code == 0x6a {
+            return Some(format!("push {}", self.simm8_text(size)?));
+        } else if (0x70..=0x7f).contains(&code) {
+            return Some(format!("j{} {}", CONDITION_NAME[(code - 0x70) as usize], self.rel_text(8)?));
+        } else if (0x80..=0x83).contains(&code) {
+            // 82 is an alias of 80
+            let rm_size = if code == 0x80 || code == 0x82 { 8 } else { size };
+            let (modrm, rm) = self.modrm()?;
+            let value = if code == 0x83 { self.simm8_text(size)? } else { self.imm_text(rm_size)? };
+            return Some(format!("{} {}, {}", ALU_NAME[modrm.opcode as usize], rm_text(rm, rm_size), value));
+        } else if code == 0x84 || code == 0x85 {
+            return self.rm_r("test", if code == 0x84 { 8 } else { size });
+        } else if code == 0x86 || code == 0x87 {
+            return self.rm_r("xchg", if code == 0x86 { 8 } else { size });
+        } else if code == 0x88 || code == 0x89 {
+            return self.rm_r("mov", if code == 0x88 { 8 } else { size });
+        } else if code == 0x8a || code == 0x8b {
+            let rm_size = if code == 0x8a { 8 } else { size };
+            return self.r_rm("mov", rm_size, rm_size);
+        } else if code == 0x8d {
+            let (modrm, rm) = self.modrm()?;
+            if let RM::Memory(address) = rm {
+                return Some(format!("lea {}, {}", sized_register_name(modrm.reg, size), address));
+            }
+            return None;
+        } else if code == 0x8f {
+            let (modrm, rm) = self.modrm()?;
+            if modrm.opcode == 0 {
+                return Some(format!("pop {}", rm_text(rm, size)));
+            }
+            return None;
+        } else if code == 0x90 {
+            return Some("nop".to_string());
+        } else if (0x91..=0x97).contains(&code) {
+            return Some(format!("xchg {}, {}", sized_register_name(0, size), sized_register_name(code - 0x90, size)));
+        } else if code == 0x98 {
+            return Some(if size == 16 { "cbw" } else { "cwde" }.to_string());
+        } else if code == 0x99 {
+            return Some(if size == 16 { "cwd" } else { "cdq" }.to_string());
+        } else if code == 0x9a || code == 0xea {
+            let offset = self.imm(size)?;
+            let selector = self.imm(16)?;
+            let name = if code == 0x9a { "call" } else { "jmp" };
+            return Some(format!("{} far {:#X}:{:#X}", name, selector, offset));
+        } else if code == 0x9c {
+            return Some(if size == 16 { "pushf" } else { "pushfd" }.to_string());
+        } else if code == 0x9d {
+            return Some(if size == 16 { "popf" } else { "popfd" }.to_string());
+        } else if (0xa0..=0xa3).contains(&code) {
+            let rm_size = if code & 1 == 0 { 8 } else { size };
+            let memory = format!("{} ptr [{:#X}]", ptr_name(rm_size), self.imm(32)?);
+            let reg = sized_register_name(0, rm_size);
+            if code < 0xa2 {
+                return Some(format!("mov {}, {}", reg, memory));
+            }
+            return Some(format!("mov {}, {}", memory, reg));
+        } else if code == 0xa8 || code == 0xa9 {
+            return self.acc_imm("test", if code == 0xa8 { 8 } else { size });
+        } else if (0xa4..=0xaf).contains(&code) {
+            let name = match code {
+                0xa4 | 0xa5 => "movs",
+                0xa6 | 0xa7 => "cmps",
+                0xaa | 0xab => "stos",
+                0xac | 0xad => "lods",
+                _ => "scas"
+            };
+            return Some(string_name(name, if code & 1 == 0 { 8 } else { size }));
+        } else if (0xb0..=0xb7).contains(&code) {
+            return Some(format!("mov {}, {}", sized_register_name(code - 0xb0, 8), self.imm_text(8)?));
+        } else if (0xb8..=0xbf).contains(&code) {
+            return Some(format!("mov {}, {}", sized_register_name(code - 0xb8, size), self.imm_text(size)?));
+        } else if code == 0xc0 || code == 0xc1 || (0xd0..=0xd3).contains(&code) {
+            let rm_size = if code & 1 == 0 { 8 } else { size };
+            let (modrm, rm) = self.modrm()?;
+            let count = if code < 0xd0 {
+                self.imm_text(8)?
+            } else if code < 0xd2 {
+                "1".to_string()
+            } else {
+                "CL".to_string()
+            };
+            return Some(format!("{} {}, {}", SHIFT_NAME[modrm.opcode as usize], rm_text(rm, rm_size), count));
+        } else if code == 0xc2 {
+            return Some(format!("ret {}", self.imm_text(16)?));
+        } else if code == 0xc3 {
+            return Some("ret".to_string());
+        } else if code == 0xc6 || code == 0xc7 {
+            let rm_size = if code == 0xc6 { 8 } else { size };
+            let (modrm, rm) = self.modrm()?;
+            if modrm.opcode == 0 {
+                return Some(format!("mov {}, {}", rm_text(rm, rm_size), self.imm_text(rm_size)?));
+            }
+            return None;
+        } else if code == 0xc8 {
+            let frame = self.imm_text(16)?;
+            return Some(format!("enter {}, {}", frame, self.imm_text(8)?));
+        } else if code == 0xc9 {
+            return Some("leave".to_string());
+        } else if code == 0xcc {
+            return Some("int3".to_string());
+        } else if code == 0xcd {
+            return Some(format!("int {}", self.imm_text(8)?));
+        } else if code == 0xd7 {
+            return Some("xlatb".to_string());
+        } else if (0xe0..=0xe3).contains(&code) {
+            let name = ["loopne", "loope", "loop", "jecxz"][(code - 0xe0) as usize];
+            return Some(format!("{} {}", name, self.rel_text(8)?));
+        } else if code == 0xe4 || code == 0xe5 {
+            let reg = sized_register_name(0, if code == 0xe4 { 8 } else { size });
+            return Some(format!("in {}, {}", reg, self.imm_text(8)?));
+        } else if code == 0xe6 || code == 0xe7 {
+            let reg = sized_register_name(0, if code == 0xe6 { 8 } else { size });
+            return Some(format!("out {}, {}", self.imm_text(8)?, reg));
+        } else if code == 0xe8 {
+            return Some(format!("call {}", self.rel_text(32)?));
+        } else if code == 0xe9 {
+            return Some(format!("jmp {}", self.rel_text(32)?));
+        } else if code == 0xeb {
+            return Some(format!("jmp {}", self.rel_text(8)?));
+        } else if code == 0xec || code == 0xed {
+            return Some(format!("in {}, DX", sized_register_name(0, if code == 0xec { 8 } else { size })));
+        } else if code == 0xee || code == 0xef {
+            return Some(format!("out DX, {}", sized_register_name(0, if code == 0xee { 8 } else { size })));
+        } else if code == 0xf4 {
+            return Some("hlt".to_string());
+        } else if code == 0xf5 {
+            return Some("cmc".to_string());
+        } else if code == 0xf6 || code == 0xf7 {
+            let rm_size = if code == 0xf6 { 8 } else { size };
+            let (modrm, rm) = self.modrm()?;
+            let name = GROUP3_NAME[modrm.opcode as usize];
+            if modrm.opcode < 2 {
+                return Some(format!("{} {}, {}", name, rm_text(rm, rm_size), self.imm_text(rm_size)?));
+            }
+            return Some(format!("{} {}", name, rm_text(rm, rm_size)));
+        } else if (0xf8..=0xfd).contains(&code) {
+            return Some(["clc", "stc", "cli", "sti", "cld", "std"][(code - 0xf8) as usize].to_string());
+        } else if code == 0xfe {
+            let (modrm, rm) = self.modrm()?;
+            if modrm.opcode < 2 {
+                return Some(format!("{} {}", ["inc", "dec"][modrm.opcode as usize], rm_text(rm, 8)));
+            }
+            return None;
+        } else if code == 0xff {
+            let (modrm, rm) = self.modrm()?;
+            return match modrm.opcode {
+                0 => Some(format!("inc {}", rm_text(rm, size))),
+                1 => Some(format!("dec {}", rm_text(rm, size))),
+                2 => Some(format!("call {}", rm_text(rm, size))),
+                3 => Some(format!("call far {}", rm_text(rm, 48))),
+                4 => Some(format!("jmp {}", rm_text(rm, size))),
+                5 => Some(format!("jmp far {}", rm_text(rm, 48))),
+                6 => Some(format!("push {}", rm_text(rm, size))),
+                _ => None
+            };
+        }
+        return None;
+    }
+
+    fn opcode0f(&mut self) -> Option<String> {
+        let size = self.operand_size;
+        let code = self.next()? as u32;
+        if code == 0x31 {
+            return Some("rdtsc".to_string());
+        } else if (0x40..=0x4f).contains(&code) {
+            return self.r_rm(&format!("cmov{}", CONDITION_NAME[(code - 0x40) as usize]), size, size);
+        } else if (0x80..=0x8f).contains(&code) {
+            return Some(format!("j{} {}", CONDITION_NAME[(code - 0x80) as usize], self.rel_text(32)?));
+        } else if (0x90..=0x9f).contains(&code) {
+            let (_, rm) = self.modrm()?;
+            return Some(format!("set{} {}", CONDITION_NAME[(code - 0x90) as usize], rm_text(rm, 8)));
+        } else if code == 0xa2 {
+            return Some("cpuid".to_string());
+        } else if code == 0xa3 || code == 0xab || code == 0xb3 || code == 0xbb {
+            return self.rm_r(BIT_NAME[((code >> 3) & 3) as usize], size);
+        } else if [0xa4, 0xa5, 0xac, 0xad].contains(&code) {
+            let name = if code < 0xac { "shld" } else { "shrd" };
+            let (modrm, rm) = self.modrm()?;
+            let count = if code & 1 == 0 { self.imm_text(8)? } else { "CL".to_string() };
+            return Some(format!("{} {}, {}, {}", name, rm_text(rm, size), sized_register_name(modrm.reg, size), count));
+        } else if code == 0xaf {
+            return self.r_rm("imul", size, size);
+        } else if code == 0xb6 || code == 0xb7 {
+            return self.r_rm("movzx", size, if code == 0xb6 { 8 } else { 16 });
+        } else if code == 0xba {
+            let (modrm, rm) = self.modrm()?;
+            if modrm.opcode >= 4 {
+                let name = BIT_NAME[(modrm.opcode - 4) as usize];
+                return Some(format!("{} {}, {}", name, rm_text(rm, size), self.imm_text(8)?));
+            }
+            return None;
+        } else if code == 0xbc || code == 0xbd {
+            return self.r_rm(if code == 0xbc { "bsf" } else { "bsr" }, size, size);
+        } else if code == 0xbe || code == 0xbf {
+            return self.r_rm("movsx", size, if code == 0xbe { 8 } else { 16 });
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+
+    fn text(code: &[u8]) -> String {
+        let insn = disassemble(code, 0x1000).unwrap();
+        assert_eq!(insn.length() as usize, code.len());
+        return insn.text;
+    }
+
+    #[test]
+    fn disassemble_alu() {
+        assert_eq!(text(&[0x01, 0xd8]), "add EAX, EBX");
+        assert_eq!(text(&[0x2b, 0x45, 0xfc]), "sub EAX, dword ptr [EBP-0x4]");
+        assert_eq!(text(&[0x30, 0xe1]), "xor CL, AH");
+        assert_eq!(text(&[0x3c, 0x7f]), "cmp AL, 0x7F");
+        assert_eq!(text(&[0x83, 0xec, 0x10]), "sub ESP, 0x10");
+        assert_eq!(text(&[0x83, 0xe0, 0xf0]), "and EAX, 0xFFFFFFF0");
+        assert_eq!(text(&[0x80, 0x3c, 0x8b, 0x00]), "cmp byte ptr [EBX+ECX*4], 0x0");
+        assert_eq!(text(&[0x66, 0x05, 0x34, 0x12]), "add AX, 0x1234");
+        assert_eq!(text(&[0x82, 0xc9, 0x01]), "or CL, 0x1");
+    }
+
+    #[test]
+    fn disassemble_mov() {
+        assert_eq!(text(&[0x89, 0xe5]), "mov EBP, ESP");
+        assert_eq!(text(&[0xb8, 0x78, 0x56, 0x34, 0x12]), "mov EAX, 0x12345678");
+        assert_eq!(text(&[0xb4, 0x01]), "mov AH, 0x1");
+        assert_eq!(text(&[0xc7, 0x05, 0x00, 0x10, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]),
+                   "mov dword ptr [0x1000], 0x1");
+        assert_eq!(text(&[0x66, 0x89, 0x1d, 0x00, 0x02, 0x00, 0x00]), "mov word ptr [0x200], BX");
+        assert_eq!(text(&[0x8d, 0x44, 0x24, 0x08]), "lea EAX, [ESP+0x8]");
+        assert_eq!(text(&[0x0f, 0xb6, 0xc1]), "movzx EAX, CL");
+    }
+
+    #[test]
+    fn disassemble_branch() {
+        assert_eq!(text(&[0x75, 0xfe]), "jnz 0x1000");
+        assert_eq!(text(&[0x0f, 0x8c, 0x10, 0x00, 0x00, 0x00]), "jl 0x1016");
+        assert_eq!(text(&[0xe8, 0xfb, 0xff, 0xff, 0xff]), "call 0x1000");
+        assert_eq!(text(&[0xff, 0xd0]), "call EAX");
+        assert_eq!(text(&[0xc3]), "ret");
+        assert_eq!(text(&[0x0f, 0x94, 0xc0]), "setz AL");
+    }
+
+    #[test]
+    fn disassemble_misc() {
+        assert_eq!(text(&[0xd1, 0xe8]), "shr EAX, 1");
+        assert_eq!(text(&[0xc1, 0xe0, 0x04]), "shl EAX, 0x4");
+        assert_eq!(text(&[0xf7, 0xf9]), "idiv ECX");
+        assert_eq!(text(&[0xf3, 0xa5]), "rep movsd");
+        assert_eq!(text(&[0xf3, 0xa6]), "repe cmpsb");
+        assert_eq!(text(&[0xcd, 0x80]), "int 0x80");
+        assert_eq!(text(&[0x6a, 0xff]), "push 0xFFFFFFFF");
+    }
+
+    #[test]
+    fn disassemble_invalid() {
+        assert_eq!(disassemble(&[], 0), None);
+        assert_eq!(disassemble(&[0x0f, 0xff], 0), None);
+        assert_eq!(disassemble(&[0x8d, 0xc0], 0), None);
+        // truncated imm32
+        assert_eq!(disassemble(&[0xb8, 0x01, 0x02], 0), None);
+    }
+
+    #[test]
+    fn instruction_display() {
+        let insn = disassemble(&[0x83, 0xc0, 0x01], 0x7c00).unwrap();
+        assert_eq!(format!("{}", insn), "00007C00: 83 C0 01                add EAX, 0x1");
+    }
+}
@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmulatorError {
+    UnknownOpcode { eip: u32, bytes: Vec<u8> },
+    UnsupportedAddressing { eip: u32, bytes: Vec<u8> },
+    MemoryOutOfBounds { eip: u32, bytes: Vec<u8>, address: u32, size: u32 },
+    // #DE: division by zero or a quotient too large for the destination
+    DivideError { eip: u32, bytes: Vec<u8> },
+}
+
+impl EmulatorError {
+    pub fn eip(&self) -> u32 {
+        return match self {
+            EmulatorError::UnknownOpcode { eip, .. } => *eip,
+            EmulatorError::UnsupportedAddressing { eip, .. } => *eip,
+            EmulatorError::MemoryOutOfBounds { eip, .. } => *eip,
+            EmulatorError::DivideError { eip, .. } => *eip,
+        };
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        return match self {
+            EmulatorError::UnknownOpcode { bytes, .. } => bytes,
+            EmulatorError::UnsupportedAddressing { bytes, .. } => bytes,
+            EmulatorError::MemoryOutOfBounds { bytes, .. } => bytes,
+            EmulatorError::DivideError { bytes, .. } => bytes,
+        };
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let data: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    return data.join(" ");
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::UnknownOpcode { eip, bytes } => {
+                write!(f, "unknown opcode at {:08X}: {}", eip, hex_bytes(bytes))
+            }
+            EmulatorError::UnsupportedAddressing { eip, bytes } => {
+                write!(f, "unsupported addressing at {:08X}: {}", eip, hex_bytes(bytes))
+            }
+            EmulatorError::MemoryOutOfBounds { eip, bytes, address, size } => {
+                write!(f, "memory access out of bounds at {:08X}: {} (address: {:08X}, size: {})",
+                       eip, hex_bytes(bytes), address, size)
+            }
+            EmulatorError::DivideError { eip, bytes } => {
+                write!(f, "divide error at {:08X}: {}", eip, hex_bytes(bytes))
+            }
+        }
+    }
+}
+
+impl Error for EmulatorError {}
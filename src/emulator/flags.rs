@@ -0,0 +1,310 @@
+pub const CF: u32 = 1;
+pub const PF: u32 = 1 << 2;
+pub const AF: u32 = 1 << 4;
+pub const ZF: u32 = 1 << 6;
+pub const SF: u32 = 1 << 7;
+pub const DF: u32 = 1 << 10;
+pub const OF: u32 = 1 << 11;
+pub const NT: u32 = 1 << 14;
+pub const AC: u32 = 1 << 18;
+pub const ID: u32 = 1 << 21;
+
+// bit 1 is reserved and always reads as set
+pub const RESERVED: u32 = 1 << 1;
+
+// flags written by the arithmetic and logic instructions
+pub const STATUS: u32 = CF | PF | AF | ZF | SF | OF;
+
+// flags POPFD may write in user mode; IF, IOPL, VM and RF keep their
+// values and TF, whose single-step trap is not emulated, stays clear
+pub const POPF: u32 = STATUS | DF | NT | AC | ID;
+
+pub fn mask(size: u32) -> u32 {
+    return if size == 32 { 0xffffffff } else { (1 << size) - 1 };
+}
+
+fn sign_bit(size: u32) -> u32 {
+    return 1 << (size - 1);
+}
+
+// PF, ZF and SF depend only on the result
+fn result_flags(result: u32, size: u32) -> u32 {
+    let mut flags = 0;
+    if (result & 0xff).count_ones() & 1 == 0 {
+        flags |= PF;
+    }
+    if result & mask(size) == 0 {
+        flags |= ZF;
+    }
+    if result & sign_bit(size) != 0 {
+        flags |= SF;
+    }
+    return flags;
+}
+
+pub fn add(target: u32, value: u32, carry: bool, size: u32) -> (u32, u32) {
+    let full = target as u64 + value as u64 + carry as u64;
+    let result = (full as u32) & mask(size);
+    let mut flags = result_flags(result, size);
+    if full > mask(size) as u64 {
+        flags |= CF;
+    }
+    if (target ^ value ^ result) & 0x10 != 0 {
+        flags |= AF;
+    }
+    if (target ^ result) & (value ^ result) & sign_bit(size) != 0 {
+        flags |= OF;
+    }
+    return (result, flags);
+}
+
+pub fn sub(target: u32, value: u32, borrow: bool, size: u32) -> (u32, u32) {
+    let result = target.wrapping_sub(value).wrapping_sub(borrow as u32) & mask(size);
+    let mut flags = result_flags(result, size);
+    if (target as u64) < value as u64 + borrow as u64 {
+        flags |= CF;
+    }
+    if (target ^ value ^ result) & 0x10 != 0 {
+        flags |= AF;
+    }
+    if (target ^ value) & (target ^ result) & sign_bit(size) != 0 {
+        flags |= OF;
+    }
+    return (result, flags);
+}
+
+// AND, OR, XOR and TEST: CF and OF are cleared, AF is undefined (cleared)
+pub fn logic(result: u32, size: u32) -> u32 {
+    return result_flags(result & mask(size), size);
+}
+
+// INC and DEC leave CF alone, so callers update STATUS & !CF only
+pub fn inc(target: u32, size: u32) -> (u32, u32) {
+    return add(target, 1, false, size);
+}
+
+pub fn dec(target: u32, size: u32) -> (u32, u32) {
+    return sub(target, 1, false, size);
+}
+
+pub fn neg(target: u32, size: u32) -> (u32, u32) {
+    return sub(0, target, false, size);
+}
+
+pub fn sign_extend(value: u32, size: u32) -> i64 {
+    let shift = 64 - size;
+    return ((value as i64) << shift) >> shift;
+}
+
+// MUL and IMUL set CF and OF when the upper half of the product is
+// significant; SF, ZF and PF are undefined and follow the lower half.
+pub fn mul(target: u32, value: u32, size: u32) -> (u64, u32) {
+    let product = (target & mask(size)) as u64 * (value & mask(size)) as u64;
+    let mut flags = result_flags(product as u32 & mask(size), size);
+    if product >> size != 0 {
+        flags |= CF | OF;
+    }
+    return (product, flags);
+}
+
+pub fn imul(target: u32, value: u32, size: u32) -> (u64, u32) {
+    let product = sign_extend(target, size) * sign_extend(value, size);
+    let low = product as u32 & mask(size);
+    let mut flags = result_flags(low, size);
+    if sign_extend(low, size) != product {
+        flags |= CF | OF;
+    }
+    return (product as u64, flags);
+}
+
+// C0, C1 and D0-D3, indexed like the ModR/M reg field: ROL, ROR, RCL, RCR,
+// SHL, SHR, SAL (= SHL) and SAR. The count is masked to five bits and
+// non-zero. Rotates only write CF and OF; OF is computed as for a count of 1.
+pub fn shift(op: u32, target: u32, count: u32, carry: bool, size: u32) -> (u32, u32) {
+    let target = target & mask(size);
+    let msb = |value: u32| value & sign_bit(size) != 0;
+    let wide = target as u64;
+    let (result, cf, of) = if op == 0 || op == 1 {
+        let n = (count % size) as u64;
+        let n = if op == 0 { n } else { (size as u64 - n) % size as u64 };
+        let result = ((wide << n | wide >> (size as u64 - n)) as u32) & mask(size);
+        if op == 0 {
+            (result, result & 1 != 0, msb(result) ^ (result & 1 != 0))
+        } else {
+            (result, msb(result), msb(result) ^ msb(result << 1))
+        }
+    } else if op == 2 || op == 3 {
+        // rotate through carry: a size + 1 bit rotation
+        let width = size as u64 + 1;
+        let value = (carry as u64) << size | wide;
+        let n = count as u64 % width;
+        let n = if op == 2 { n } else { (width - n) % width };
+        let rotated = (value << n | value >> (width - n)) & ((1 << width) - 1);
+        let result = rotated as u32 & mask(size);
+        let cf = rotated >> size & 1 != 0;
+        if op == 2 {
+            (result, cf, msb(result) ^ cf)
+        } else {
+            (result, cf, msb(result) ^ msb(result << 1))
+        }
+    } else if op == 4 || op == 6 {
+        let full = wide << count;
+        let result = full as u32 & mask(size);
+        let cf = full >> size & 1 != 0;
+        (result, cf, msb(result) ^ cf)
+    } else if op == 5 {
+        ((wide >> count) as u32, wide >> (count - 1) & 1 != 0, msb(target))
+    } else {
+        let signed = sign_extend(target, size);
+        ((signed >> count) as u32 & mask(size), signed >> (count - 1) & 1 != 0, false)
+    };
+
+    let mut flags = if op < 4 { 0 } else { result_flags(result, size) };
+    if cf {
+        flags |= CF;
+    }
+    if of {
+        flags |= OF;
+    }
+    return (result, flags);
+}
+
+// SHLD and SHRD shift bits of `source` into `target`; the count is masked
+// to five bits and non-zero.
+pub fn double_shift(left: bool, target: u32, source: u32, count: u32, size: u32) -> (u32, u32) {
+    let target = target & mask(size);
+    let source = source & mask(size);
+    let count = count as u64;
+    let (result, cf) = if left {
+        let full = (target as u64) << size | source as u64;
+        ((full << count >> size) as u32 & mask(size), full >> (2 * size as u64 - count) & 1 != 0)
+    } else {
+        let full = (source as u64) << size | target as u64;
+        ((full >> count) as u32 & mask(size), full >> (count - 1) & 1 != 0)
+    };
+    let mut flags = result_flags(result, size);
+    if cf {
+        flags |= CF;
+    }
+    if (result ^ target) & sign_bit(size) != 0 {
+        flags |= OF;
+    }
+    return (result, flags);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_flags() {
+        assert_eq!(add(1, 2, false, 32), (3, PF));
+        assert_eq!(add(0xffffffff, 1, false, 32), (0, CF | PF | AF | ZF));
+        assert_eq!(add(0x7fffffff, 1, false, 32), (0x80000000, PF | AF | SF | OF));
+        assert_eq!(add(0x80000000, 0x80000000, false, 32), (0, CF | PF | ZF | OF));
+        assert_eq!(add(0x0f, 0x01, false, 32), (0x10, AF));
+    }
+
+    #[test]
+    fn adc_flags() {
+        assert_eq!(add(0xfffffffe, 1, true, 32), (0, CF | PF | AF | ZF));
+        assert_eq!(add(0xffffffff, 0xffffffff, true, 32), (0xffffffff, CF | PF | AF | SF));
+    }
+
+    #[test]
+    fn sub_flags() {
+        assert_eq!(sub(3, 3, false, 32), (0, PF | ZF));
+        assert_eq!(sub(0, 1, false, 32), (0xffffffff, CF | PF | AF | SF));
+        assert_eq!(sub(0x80000000, 1, false, 32), (0x7fffffff, PF | AF | OF));
+        assert_eq!(sub(0x7fffffff, 0xffffffff, false, 32), (0x80000000, CF | PF | SF | OF));
+        assert_eq!(sub(0x10, 0x01, false, 32), (0x0f, PF | AF));
+    }
+
+    #[test]
+    fn sbb_flags() {
+        assert_eq!(sub(1, 0, true, 32), (0, PF | ZF));
+        assert_eq!(sub(0, 0, true, 32), (0xffffffff, CF | PF | AF | SF));
+    }
+
+    #[test]
+    fn logic_flags() {
+        assert_eq!(logic(0, 32), PF | ZF);
+        assert_eq!(logic(0x80000000, 32), PF | SF);
+        assert_eq!(logic(0x01, 32), 0);
+        assert_eq!(logic(0x03, 32), PF);
+    }
+
+    #[test]
+    fn inc_dec_flags() {
+        assert_eq!(inc(0x7fffffff, 32), (0x80000000, PF | AF | SF | OF));
+        assert_eq!(inc(0xffffffff, 32), (0, CF | PF | AF | ZF));
+        assert_eq!(dec(1, 32), (0, PF | ZF));
+        assert_eq!(dec(0x80000000, 32), (0x7fffffff, PF | AF | OF));
+    }
+
+    #[test]
+    fn neg_flags() {
+        assert_eq!(neg(0, 32), (0, PF | ZF));
+        assert_eq!(neg(1, 32), (0xffffffff, CF | PF | AF | SF));
+        assert_eq!(neg(0x80000000, 32), (0x80000000, CF | PF | SF | OF));
+    }
+
+    #[test]
+    fn mul_flags() {
+        assert_eq!(mul(3, 4, 32), (12, PF));
+        assert_eq!(mul(0x10000, 0x10000, 32), (0x100000000, CF | PF | ZF | OF));
+        assert_eq!(mul(0x80, 2, 8), (0x100, CF | PF | ZF | OF));
+        assert_eq!(mul(0xffff, 0xffff, 16), (0xfffe0001, CF | OF));
+    }
+
+    #[test]
+    fn imul_flags() {
+        assert_eq!(imul(0xfffffffe, 3, 32), (0xfffffffffffffffa, PF | SF));
+        assert_eq!(imul(0x40000000, 2, 32), (0x80000000, CF | PF | SF | OF));
+        assert_eq!(imul(0xff, 0xff, 8), (1, 0));
+        assert_eq!(imul(0x80, 0xff, 8), (0x80, CF | SF | OF));
+        assert_eq!(sign_extend(0x8000, 16), -0x8000);
+    }
+
+    #[test]
+    fn rotate_flags() {
+        assert_eq!(shift(0, 0x80000001, 1, false, 32), (0x00000003, CF | OF));
+        assert_eq!(shift(0, 0x81, 8, false, 8), (0x81, CF));
+        assert_eq!(shift(1, 0x01, 1, false, 8), (0x80, CF | OF));
+        assert_eq!(shift(1, 0x12345678, 8, false, 32), (0x78123456, OF));
+        assert_eq!(shift(2, 0x80, 1, false, 8), (0x00, CF | OF));
+        assert_eq!(shift(2, 0x00, 1, true, 8), (0x01, 0));
+        assert_eq!(shift(2, 0x01, 9, false, 8), (0x01, 0));
+        assert_eq!(shift(3, 0x01, 1, false, 16), (0x0000, CF));
+        assert_eq!(shift(3, 0x00, 1, true, 32), (0x80000000, OF));
+    }
+
+    #[test]
+    fn shift_flags() {
+        assert_eq!(shift(4, 0x80000000, 1, false, 32), (0, CF | PF | ZF | OF));
+        assert_eq!(shift(4, 0x40, 1, false, 8), (0x80, SF | OF));
+        assert_eq!(shift(6, 0x01, 4, false, 16), (0x10, 0));
+        assert_eq!(shift(4, 0xff, 9, false, 8), (0, PF | ZF));
+        assert_eq!(shift(5, 0x81, 1, false, 8), (0x40, CF | OF));
+        assert_eq!(shift(5, 0x80000000, 31, false, 32), (1, OF));
+        assert_eq!(shift(7, 0x81, 1, false, 8), (0xc0, CF | PF | SF));
+        assert_eq!(shift(7, 0x80000000, 31, false, 32), (0xffffffff, PF | SF));
+    }
+
+    #[test]
+    fn double_shift_flags() {
+        assert_eq!(double_shift(true, 0x12345678, 0x9abcdef0, 8, 32), (0x3456789a, PF));
+        assert_eq!(double_shift(true, 0x4000, 0x8000, 1, 16), (0x8001, SF | OF));
+        assert_eq!(double_shift(true, 0x8000, 0, 1, 16), (0, CF | PF | ZF | OF));
+        assert_eq!(double_shift(false, 0x12345678, 0x9abcdef0, 8, 32), (0xf0123456, PF | SF | OF));
+        assert_eq!(double_shift(false, 0x0001, 0x0000, 1, 16), (0, CF | PF | ZF));
+    }
+
+    #[test]
+    fn byte_and_word_flags() {
+        assert_eq!(add(0xff, 1, false, 8), (0, CF | PF | AF | ZF));
+        assert_eq!(add(0x7f, 1, false, 8), (0x80, AF | SF | OF));
+        assert_eq!(sub(0, 1, false, 16), (0xffff, CF | PF | AF | SF));
+        assert_eq!(logic(0x8000, 16), PF | SF);
+    }
+}
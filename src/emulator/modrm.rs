@@ -1,5 +1,6 @@
+use std::fmt;
+
 use super::register_name;
-use super::DEBUG;
 
 #[derive(Debug)]
 pub struct ModRM {
@@ -9,6 +10,28 @@ pub struct ModRM {
     pub opcode: u32
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SIB {
+    pub scale: u32,
+    pub index: u32,
+    pub base: u32
+}
+
+// [base + index * scale + disp]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    pub base: Option<u32>,
+    pub index: Option<u32>,
+    pub scale: u32,
+    pub disp: i32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RM {
+    Register(u32),
+    Memory(Address)
+}
+
 impl ModRM {
     pub fn new(code: u32) -> Self {
         let mut modrm = Self {
@@ -18,9 +41,6 @@ impl ModRM {
             opcode: 0
         };
 
-        if DEBUG {
-            println!("ModR/M: {:02X} {:#8b}", code, code);
-        }
         let mod_mask = 0b11000000;
         modrm.mode = (code & mod_mask) >> 6;
 
@@ -31,23 +51,109 @@ impl ModRM {
         let rm_mask = 0b00000111;
         modrm.rm = code & rm_mask;
 
-        let reg_name1 = register_name(modrm.reg);
-        let reg_name2 = register_name(modrm.rm);
+        return modrm;
+    }
 
-        if DEBUG {
-            println!("ModR/M: {:?}", modrm);
-            println!("Mod: {:02b}, REG: {:03b} (opcode: {}, {}), R/M: {:03b} ({})",
-                     modrm.mode, modrm.reg, modrm.opcode, reg_name1,
-                     modrm.rm, reg_name2);
+    // Decodes the SIB byte and displacement that follow the ModR/M byte.
+    // `next` returns the following instruction bytes one at a time.
+    pub fn decode<E, F>(&self, mut next: F) -> Result<RM, E>
+        where F: FnMut() -> Result<u8, E> {
+        if self.mode == 0b11 {
+            return Ok(RM::Register(self.rm));
         }
 
-        return modrm;
+        let mut address = Address {
+            base: Some(self.rm),
+            index: None,
+            scale: 1,
+            disp: 0
+        };
+        if self.rm == 0b100 {
+            let sib = SIB::new(next()? as u32);
+            address.base = Some(sib.base);
+            if sib.index != 0b100 {
+                address.index = Some(sib.index);
+                address.scale = 1 << sib.scale;
+            }
+            if self.mode == 0b00 && sib.base == 0b101 {
+                address.base = None;
+                address.disp = read_disp32(&mut next)?;
+            }
+        } else if self.mode == 0b00 && self.rm == 0b101 {
+            address.base = None;
+            address.disp = read_disp32(&mut next)?;
+        }
+
+        if self.mode == 0b01 {
+            address.disp = next()? as i8 as i32;
+        } else if self.mode == 0b10 {
+            address.disp = read_disp32(&mut next)?;
+        }
+        return Ok(RM::Memory(address));
+    }
+}
+
+fn read_disp32<E, F>(next: &mut F) -> Result<i32, E>
+    where F: FnMut() -> Result<u8, E> {
+    let mut value: u32 = 0;
+    for i in 0..4 {
+        value |= (next()? as u32) << (8 * i);
+    }
+    return Ok(value as i32);
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut text = String::new();
+        if let Some(base) = self.base {
+            text.push_str(register_name(base));
+        }
+        if let Some(index) = self.index {
+            if !text.is_empty() {
+                text.push('+');
+            }
+            text.push_str(register_name(index));
+            if self.scale != 1 {
+                text.push_str(&format!("*{}", self.scale));
+            }
+        }
+        if text.is_empty() {
+            text = format!("{:#X}", self.disp as u32);
+        } else if self.disp < 0 {
+            text.push_str(&format!("-{:#X}", (self.disp as i64).abs()));
+        } else if self.disp > 0 {
+            text.push_str(&format!("+{:#X}", self.disp));
+        }
+        return write!(f, "[{}]", text);
+    }
+}
+
+impl SIB {
+    pub fn new(code: u32) -> Self {
+        return Self {
+            scale: (code & 0b11000000) >> 6,
+            index: (code & 0b00111000) >> 3,
+            base: code & 0b00000111
+        };
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ModRM;
+    use super::{Address, ModRM, RM, SIB};
+
+    fn decode(bytes: &[u8]) -> RM {
+        let modrm = ModRM::new(bytes[0] as u32);
+        let mut rest = bytes[1..].iter();
+        let rm = modrm.decode(|| rest.next().copied().ok_or(())).unwrap();
+        assert_eq!(rest.next(), None);
+        return rm;
+    }
+
+    fn address(base: Option<u32>, index: Option<u32>, scale: u32, disp: i32) -> RM {
+        return RM::Memory(Address { base, index, scale, disp });
+    }
+
     #[test]
     fn modrm_new() {
         let modrm = ModRM::new(0x61);
@@ -56,4 +162,54 @@ mod tests {
         assert_eq!(modrm.opcode, 0b100);
         assert_eq!(modrm.rm, 0b001);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sib_new() {
+        assert_eq!(SIB::new(0x8b), SIB { scale: 0b10, index: 0b001, base: 0b011 });
+    }
+
+    #[test]
+    fn decode_register() {
+        assert_eq!(decode(&[0xc1]), RM::Register(1));
+    }
+
+    #[test]
+    fn decode_mod00() {
+        // [ebx]
+        assert_eq!(decode(&[0x03]), address(Some(3), None, 1, 0));
+        // [0x12345678]
+        assert_eq!(decode(&[0x05, 0x78, 0x56, 0x34, 0x12]), address(None, None, 1, 0x12345678));
+        // [ebx+ecx*4]
+        assert_eq!(decode(&[0x04, 0x8b]), address(Some(3), Some(1), 4, 0));
+        // [ecx*8+0x100]
+        assert_eq!(decode(&[0x04, 0xcd, 0x00, 0x01, 0x00, 0x00]), address(None, Some(1), 8, 0x100));
+        // [esp]
+        assert_eq!(decode(&[0x04, 0x24]), address(Some(4), None, 1, 0));
+    }
+
+    #[test]
+    fn decode_mod01() {
+        // [ebp-4]
+        assert_eq!(decode(&[0x45, 0xfc]), address(Some(5), None, 1, -4));
+        // [esp+8]
+        assert_eq!(decode(&[0x44, 0x24, 0x08]), address(Some(4), None, 1, 8));
+        // [ebp+esi*2+0x10]
+        assert_eq!(decode(&[0x44, 0x75, 0x10]), address(Some(5), Some(6), 2, 0x10));
+    }
+
+    #[test]
+    fn address_display() {
+        assert_eq!(format!("{}", Address { base: Some(5), index: None, scale: 1, disp: -4 }), "[EBP-0x4]");
+        assert_eq!(format!("{}", Address { base: Some(3), index: Some(1), scale: 4, disp: 8 }), "[EBX+ECX*4+0x8]");
+        assert_eq!(format!("{}", Address { base: None, index: None, scale: 1, disp: 0x1000 }), "[0x1000]");
+        assert_eq!(format!("{}", Address { base: None, index: Some(6), scale: 2, disp: 0 }), "[ESI*2]");
+    }
+
+    #[test]
+    fn decode_mod10() {
+        // [eax+0x1000]
+        assert_eq!(decode(&[0x80, 0x00, 0x10, 0x00, 0x00]), address(Some(0), None, 1, 0x1000));
+        // [edi+eax*1-0x100]
+        assert_eq!(decode(&[0x84, 0x07, 0x00, 0xff, 0xff, 0xff]), address(Some(7), Some(0), 1, -0x100));
+    }
+}
@@ -0,0 +1,465 @@
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Register::*;
+use super::{Emulator, EmulatorError, StopReason, TraceLevel};
+
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const ENOMEM: u32 = 12;
+const EFAULT: u32 = 14;
+const EINVAL: u32 = 22;
+const ENOTTY: u32 = 25;
+const ENOSYS: u32 = 38;
+
+const SYS_EXIT: u32 = 1;
+const SYS_READ: u32 = 3;
+const SYS_WRITE: u32 = 4;
+const SYS_TIME: u32 = 13;
+const SYS_GETPID: u32 = 20;
+const SYS_BRK: u32 = 45;
+const SYS_IOCTL: u32 = 54;
+const SYS_GETTIMEOFDAY: u32 = 78;
+const SYS_MUNMAP: u32 = 91;
+const SYS_UNAME: u32 = 122;
+const SYS_WRITEV: u32 = 146;
+const SYS_MMAP2: u32 = 192;
+const SYS_GETUID32: u32 = 199;
+const SYS_GETGID32: u32 = 200;
+const SYS_GETEUID32: u32 = 201;
+const SYS_GETEGID32: u32 = 202;
+const SYS_GETTID: u32 = 224;
+const SYS_EXIT_GROUP: u32 = 252;
+const SYS_SET_TID_ADDRESS: u32 = 258;
+const SYS_CLOCK_GETTIME: u32 = 265;
+
+const MAP_ANONYMOUS: u32 = 0x20;
+
+const PAGE_SIZE: u32 = 0x1000;
+// reserved for the stack at the top of memory; mmap2 allocates below it
+pub const STACK_SIZE: u32 = 0x10000;
+
+const PID: u32 = 1;
+
+const AT_NULL: u32 = 0;
+const AT_PAGESZ: u32 = 6;
+const AT_ENTRY: u32 = 9;
+const AT_UID: u32 = 11;
+const AT_EUID: u32 = 12;
+const AT_GID: u32 = 13;
+const AT_EGID: u32 = 14;
+const AT_RANDOM: u32 = 25;
+
+fn page_align(value: u32) -> u32 {
+    return value.wrapping_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+}
+
+fn errno(value: u32) -> u32 {
+    return value.wrapping_neg();
+}
+
+// guest process state used by the system calls
+pub struct Process {
+    pub brk_start: u32,
+    pub brk: u32,
+    pub mmap_top: u32,
+    pub stdin: Box<dyn Read>,
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>
+}
+
+impl Process {
+    pub fn new(mem_size: u32) -> Self {
+        return Self {
+            brk_start: 0,
+            brk: 0,
+            mmap_top: mem_size.saturating_sub(STACK_SIZE) & !(PAGE_SIZE - 1),
+            stdin: Box::new(io::stdin()),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr())
+        };
+    }
+}
+
+impl Emulator {
+    pub fn set_stdin(&mut self, stdin: Box<dyn Read>) {
+        self.process.stdin = stdin;
+    }
+
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write>) {
+        self.process.stdout = stdout;
+    }
+
+    pub fn set_stderr(&mut self, stderr: Box<dyn Write>) {
+        self.process.stderr = stderr;
+    }
+
+    // start of the heap grown by brk, normally the end of the loaded image
+    pub fn set_program_break(&mut self, address: u32) {
+        self.process.brk_start = page_align(address);
+        self.process.brk = self.process.brk_start;
+    }
+
+    pub fn program_break(&self) -> u32 {
+        return self.process.brk;
+    }
+
+    // Builds the initial stack of a Linux process: argc, argv, envp and the
+    // auxiliary vector, with the strings stored at the top of memory.
+    pub fn init_process_stack(&mut self, args: &[&str], env: &[&str]) -> Result<(), EmulatorError> {
+        // build below the initial ESP, which is the top of memory unless moved
+        let mut sp = self.get_register(ESP).wrapping_add(4);
+        let mut strings = Vec::new();
+        for text in args.iter().chain(env.iter()) {
+            sp = sp.wrapping_sub(text.len() as u32 + 1);
+            self.load(sp, text.as_bytes())?;
+            self.load(sp.wrapping_add(text.len() as u32), &[0])?;
+            strings.push(sp);
+        }
+        // AT_RANDOM points at 16 bytes; ours are fixed so runs are reproducible
+        sp = sp.wrapping_sub(16) & !3;
+        let random = sp;
+        self.load(random, &[0x5a; 16])?;
+
+        let mut words = vec![args.len() as u32];
+        words.extend_from_slice(&strings[..args.len()]);
+        words.push(0);
+        words.extend_from_slice(&strings[args.len()..]);
+        words.push(0);
+        let auxv = [
+            (AT_PAGESZ, PAGE_SIZE), (AT_ENTRY, self.eip), (AT_UID, 0), (AT_EUID, 0),
+            (AT_GID, 0), (AT_EGID, 0), (AT_RANDOM, random), (AT_NULL, 0)
+        ];
+        for (key, value) in auxv.iter() {
+            words.push(*key);
+            words.push(*value);
+        }
+
+        let size = words.len() as u32 * 4;
+        sp = sp.wrapping_sub(size) & !15;
+        for (i, word) in words.iter().enumerate() {
+            self.memory_set32(sp.wrapping_add(4 * i as u32), *word)?;
+        }
+        self.set_register(ESP, sp);
+        return Ok(());
+    }
+
+    // int 0x80: the number is in EAX and the arguments in EBX, ECX, EDX,
+    // ESI, EDI and EBP; failures return -errno in EAX.
+    pub(super) fn syscall(&mut self) -> Result<Option<StopReason>, EmulatorError> {
+        let number = self.get_register(EAX);
+        let args = [
+            self.get_register(EBX), self.get_register(ECX), self.get_register(EDX),
+            self.get_register(ESI), self.get_register(EDI), self.get_register(EBP)
+        ];
+
+        let result = if number == SYS_EXIT || number == SYS_EXIT_GROUP {
+            if self.tracing(TraceLevel::Decode) {
+                eprintln!("syscall exit({})", args[0]);
+            }
+            return Ok(Some(StopReason::Exit(args[0])));
+        } else if number == SYS_READ {
+            self.sys_read(args[0], args[1], args[2])
+        } else if number == SYS_WRITE {
+            self.sys_write(args[0], args[1], args[2])
+        } else if number == SYS_WRITEV {
+            self.sys_writev(args[0], args[1], args[2])
+        } else if number == SYS_BRK {
+            self.sys_brk(args[0])
+        } else if number == SYS_MMAP2 {
+            self.sys_mmap2(args[1], args[3], args[4])
+        } else if number == SYS_MUNMAP {
+            0
+        } else if number == SYS_UNAME {
+            self.sys_uname(args[0])
+        } else if number == SYS_GETPID || number == SYS_GETTID || number == SYS_SET_TID_ADDRESS {
+            PID
+        } else if [SYS_GETUID32, SYS_GETGID32, SYS_GETEUID32, SYS_GETEGID32].contains(&number) {
+            0
+        } else if number == SYS_TIME {
+            self.sys_time(args[0])
+        } else if number == SYS_GETTIMEOFDAY {
+            self.sys_gettimeofday(args[0])
+        } else if number == SYS_CLOCK_GETTIME {
+            self.sys_clock_gettime(args[1])
+        } else if number == SYS_IOCTL {
+            errno(ENOTTY)
+        } else {
+            errno(ENOSYS)
+        };
+
+        if self.tracing(TraceLevel::Decode) {
+            eprintln!("syscall {}({:X}, {:X}, {:X}) => {:X}", number, args[0], args[1], args[2], result);
+        }
+        self.set_register(EAX, result);
+        return Ok(None);
+    }
+
+    fn sys_read(&mut self, fd: u32, buf: u32, count: u32) -> u32 {
+        if fd != 0 {
+            return errno(EBADF);
+        }
+        if self.memory_slice(buf, count).is_err() {
+            return errno(EFAULT);
+        }
+        let mut data = vec![0; count as usize];
+        let size = match self.process.stdin.read(&mut data) {
+            Ok(size) => size,
+            Err(_) => return errno(EIO)
+        };
+        if self.load(buf, &data[..size]).is_err() {
+            return errno(EFAULT);
+        }
+        return size as u32;
+    }
+
+    fn write_fd(&mut self, fd: u32, buf: u32, count: u32) -> u32 {
+        if fd != 1 && fd != 2 {
+            return errno(EBADF);
+        }
+        let data = match self.memory_slice(buf, count) {
+            Ok(data) => data.to_vec(),
+            Err(_) => return errno(EFAULT)
+        };
+        let out = if fd == 1 { &mut self.process.stdout } else { &mut self.process.stderr };
+        if out.write_all(&data).and_then(|_| out.flush()).is_err() {
+            return errno(EIO);
+        }
+        return count;
+    }
+
+    fn sys_write(&mut self, fd: u32, buf: u32, count: u32) -> u32 {
+        return self.write_fd(fd, buf, count);
+    }
+
+    fn sys_writev(&mut self, fd: u32, iov: u32, iovcnt: u32) -> u32 {
+        let mut total: u32 = 0;
+        for i in 0..iovcnt {
+            let entry = iov.wrapping_add(8 * i);
+            let (base, len) = match (self.memory_u32(entry), self.memory_u32(entry.wrapping_add(4))) {
+                (Ok(base), Ok(len)) => (base, len),
+                _ => return errno(EFAULT)
+            };
+            let result = self.write_fd(fd, base, len);
+            if (result as i32) < 0 {
+                return result;
+            }
+            total = total.wrapping_add(result);
+        }
+        return total;
+    }
+
+    // the break may move between its start and the lowest mmap2 mapping;
+    // otherwise the current break is returned unchanged
+    fn sys_brk(&mut self, address: u32) -> u32 {
+        if address >= self.process.brk_start && address <= self.process.mmap_top {
+            if address > self.process.brk {
+                let size = address - self.process.brk;
+                let _ = self.fill(self.process.brk, size, 0);
+            }
+            self.process.brk = address;
+        }
+        return self.process.brk;
+    }
+
+    // only anonymous mappings, placed downward from below the stack; the
+    // address hint is ignored
+    fn sys_mmap2(&mut self, length: u32, flags: u32, fd: u32) -> u32 {
+        if flags & MAP_ANONYMOUS == 0 || fd as i32 != -1 {
+            return errno(ENOSYS);
+        }
+        if length == 0 {
+            return errno(EINVAL);
+        }
+        let size = page_align(length);
+        let start = match self.process.mmap_top.checked_sub(size) {
+            Some(start) if size != 0 && start >= page_align(self.process.brk) => start,
+            _ => return errno(ENOMEM)
+        };
+        if self.fill(start, size, 0).is_err() {
+            return errno(ENOMEM);
+        }
+        self.process.mmap_top = start;
+        return start;
+    }
+
+    fn sys_uname(&mut self, buf: u32) -> u32 {
+        let fields = ["Linux", "remu386", "4.19.0", "#1", "i686", ""];
+        let mut data = vec![0; fields.len() * 65];
+        for (i, field) in fields.iter().enumerate() {
+            data[i * 65..i * 65 + field.len()].copy_from_slice(field.as_bytes());
+        }
+        if self.load(buf, &data).is_err() {
+            return errno(EFAULT);
+        }
+        return 0;
+    }
+
+    fn sys_time(&mut self, tloc: u32) -> u32 {
+        let seconds = now().0;
+        if tloc != 0 && self.memory_set32(tloc, seconds).is_err() {
+            return errno(EFAULT);
+        }
+        return seconds;
+    }
+
+    fn sys_gettimeofday(&mut self, tv: u32) -> u32 {
+        let (seconds, nanos) = now();
+        if tv != 0 && self.store_pair(tv, seconds, nanos / 1000).is_err() {
+            return errno(EFAULT);
+        }
+        return 0;
+    }
+
+    fn sys_clock_gettime(&mut self, tp: u32) -> u32 {
+        let (seconds, nanos) = now();
+        if self.store_pair(tp, seconds, nanos).is_err() {
+            return errno(EFAULT);
+        }
+        return 0;
+    }
+
+    fn store_pair(&mut self, address: u32, first: u32, second: u32) -> Result<(), EmulatorError> {
+        self.memory_set32(address, first)?;
+        self.memory_set32(address.wrapping_add(4), second)?;
+        return Ok(());
+    }
+}
+
+fn now() -> (u32, u32) {
+    return match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => (time.as_secs() as u32, time.subsec_nanos()),
+        Err(_) => (0, 0)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use super::super::Register::*;
+    use super::super::{Emulator, StopReason};
+
+    const TEST_MEMSIZE: u32 = 0x40000;
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(data);
+            return Ok(data.len());
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    // int 0x80
+    const INT80: [u8; 2] = [0xcd, 0x80];
+
+    fn syscall(emu: &mut Emulator, number: u32, args: &[u32]) -> u32 {
+        let registers = [EBX, ECX, EDX, ESI, EDI, EBP];
+        for (reg, value) in registers.iter().zip(args.iter()) {
+            emu.set_register(*reg, *value);
+        }
+        emu.set_register(EAX, number);
+        emu.load(0, &INT80).unwrap();
+        emu.set_eip(0);
+        assert_eq!(emu.step(), Ok(None));
+        return emu.get_register(EAX);
+    }
+
+    #[test]
+    fn exit() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // mov ebx,42; mov eax,1; int 0x80
+        emu.load(0, &[0xbb, 0x2a, 0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xcd, 0x80]).unwrap();
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(42)));
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        let stdout = Output::default();
+        emu.set_stdout(Box::new(stdout.clone()));
+        emu.set_stdin(Box::new(&b"input"[..]));
+        emu.load(0x100, b"hello\n").unwrap();
+        assert_eq!(syscall(&mut emu, 4, &[1, 0x100, 6]), 6);
+        assert_eq!(stdout.0.borrow().as_slice(), b"hello\n");
+
+        assert_eq!(syscall(&mut emu, 3, &[0, 0x200, 16]), 5);
+        assert_eq!(emu.memory_slice(0x200, 5), Ok(&b"input"[..]));
+
+        // writev with two buffers
+        emu.load(0x300, &[0x00, 0x01, 0x00, 0x00, 0x02, 0, 0, 0, 0x00, 0x02, 0x00, 0x00, 0x03, 0, 0, 0]).unwrap();
+        assert_eq!(syscall(&mut emu, 146, &[1, 0x300, 2]), 5);
+        assert_eq!(stdout.0.borrow().as_slice(), b"hello\nheinp");
+    }
+
+    #[test]
+    fn errors() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        assert_eq!(syscall(&mut emu, 4, &[5, 0x100, 1]), -9i32 as u32);
+        assert_eq!(syscall(&mut emu, 4, &[1, TEST_MEMSIZE - 1, 2]), -14i32 as u32);
+        assert_eq!(syscall(&mut emu, 9999, &[]), -38i32 as u32);
+    }
+
+    #[test]
+    fn brk_and_mmap2() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.set_program_break(0x10001);
+        assert_eq!(syscall(&mut emu, 45, &[0]), 0x11000);
+        assert_eq!(syscall(&mut emu, 45, &[0x12000]), 0x12000);
+        emu.memory_set8(0x11800, 1).unwrap();
+        assert_eq!(syscall(&mut emu, 45, &[0x11000]), 0x11000);
+        assert_eq!(syscall(&mut emu, 45, &[0x12000]), 0x12000);
+        assert_eq!(emu.memory_u8(0x11800), Ok(0));
+        // beyond the mmap area the break stays put
+        assert_eq!(syscall(&mut emu, 45, &[TEST_MEMSIZE]), 0x12000);
+
+        // mmap2(NULL, 0x1800, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0)
+        let first = syscall(&mut emu, 192, &[0, 0x1800, 3, 0x22, 0xffffffff, 0]);
+        assert_eq!(first, TEST_MEMSIZE - 0x10000 - 0x2000);
+        let second = syscall(&mut emu, 192, &[0, 0x1000, 3, 0x22, 0xffffffff, 0]);
+        assert_eq!(second, first - 0x1000);
+        assert_eq!(syscall(&mut emu, 192, &[0, 0x1000, 3, 0x02, 3, 0]), -38i32 as u32);
+        assert_eq!(syscall(&mut emu, 192, &[0, TEST_MEMSIZE, 3, 0x22, 0xffffffff, 0]), -12i32 as u32);
+    }
+
+    #[test]
+    fn uname_and_ids() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        assert_eq!(syscall(&mut emu, 122, &[0x1000]), 0);
+        assert_eq!(emu.memory_slice(0x1000, 6), Ok(&b"Linux\0"[..]));
+        assert_eq!(emu.memory_slice(0x1000 + 4 * 65, 5), Ok(&b"i686\0"[..]));
+        assert_eq!(syscall(&mut emu, 20, &[]), 1);
+        assert_eq!(syscall(&mut emu, 199, &[]), 0);
+    }
+
+    #[test]
+    fn process_stack() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.set_eip(0x1234);
+        emu.init_process_stack(&["prog", "arg"], &["A=1"]).unwrap();
+        let sp = emu.get_register(ESP);
+        assert_eq!(sp % 16, 0);
+        assert_eq!(emu.memory_u32(sp), Ok(2));
+        let argv0 = emu.memory_u32(sp + 4).unwrap();
+        let argv1 = emu.memory_u32(sp + 8).unwrap();
+        assert_eq!(emu.memory_slice(argv0, 5), Ok(&b"prog\0"[..]));
+        assert_eq!(emu.memory_slice(argv1, 4), Ok(&b"arg\0"[..]));
+        assert_eq!(emu.memory_u32(sp + 12), Ok(0));
+        let envp0 = emu.memory_u32(sp + 16).unwrap();
+        assert_eq!(emu.memory_slice(envp0, 4), Ok(&b"A=1\0"[..]));
+        assert_eq!(emu.memory_u32(sp + 20), Ok(0));
+        // auxv starts with AT_PAGESZ and AT_ENTRY
+        assert_eq!(emu.memory_u32(sp + 24), Ok(6));
+        assert_eq!(emu.memory_u32(sp + 28), Ok(0x1000));
+        assert_eq!(emu.memory_u32(sp + 32), Ok(9));
+        assert_eq!(emu.memory_u32(sp + 36), Ok(0x1234));
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceLevel {
+    Off,
+    Instructions,
+    Decode,
+    Memory
+}
+
+impl FromStr for TraceLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_ascii_lowercase().as_str() {
+            "off" | "0" => Ok(TraceLevel::Off),
+            "instructions" | "insn" | "1" => Ok(TraceLevel::Instructions),
+            "decode" | "2" => Ok(TraceLevel::Decode),
+            "memory" | "mem" | "3" => Ok(TraceLevel::Memory),
+            _ => Err(format!("unknown trace level: {}", s))
+        };
+    }
+}
+
+impl fmt::Display for TraceLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TraceLevel::Off => "off",
+            TraceLevel::Instructions => "instructions",
+            TraceLevel::Decode => "decode",
+            TraceLevel::Memory => "memory"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceLevel;
+
+    #[test]
+    fn trace_level_from_str() {
+        assert_eq!("off".parse(), Ok(TraceLevel::Off));
+        assert_eq!("Instructions".parse(), Ok(TraceLevel::Instructions));
+        assert_eq!("2".parse(), Ok(TraceLevel::Decode));
+        assert_eq!("mem".parse(), Ok(TraceLevel::Memory));
+        assert!("verbose".parse::<TraceLevel>().is_err());
+    }
+
+    #[test]
+    fn trace_level_order() {
+        assert!(TraceLevel::Off < TraceLevel::Instructions);
+        assert!(TraceLevel::Instructions < TraceLevel::Decode);
+        assert!(TraceLevel::Decode < TraceLevel::Memory);
+    }
+}
@@ -0,0 +1,487 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use crate::emulator::Register::*;
+use crate::emulator::{Emulator, EmulatorError, Register, StopReason, WatchKind, Watchpoint};
+
+// gdb's i386 register order; the segment registers 10-15 read as zero
+const REGISTERS: [Register; 8] = [EAX, ECX, EDX, EBX, ESP, EBP, ESI, EDI];
+const EIP_REGNUM: usize = 8;
+const EFLAGS_REGNUM: usize = 9;
+const REGISTER_COUNT: usize = 16;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+// gdb sends this byte, outside any packet, to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+// instructions run by `c` between checks for an interrupt
+const SLICE: u64 = 10000;
+
+// A stream gdb is connected through, which can be checked for an interrupt
+// without blocking. While the target runs gdb sends nothing else, so any
+// other byte read here is dropped.
+pub trait Connection: Read + Write {
+    fn interrupted(&mut self) -> io::Result<bool>;
+}
+
+// reads whatever has arrived on a non-blocking stream; a closed stream also
+// stops the target so that the stub notices gdb has gone
+fn poll_interrupt<S: Read>(stream: &mut S) -> io::Result<bool> {
+    let mut byte = [0u8; 1];
+    loop {
+        match stream.read(&mut byte) {
+            Ok(0) => return Ok(true),
+            Ok(_) if byte[0] == INTERRUPT => return Ok(true),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let result = poll_interrupt(self);
+        self.set_nonblocking(false)?;
+        return result;
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let result = poll_interrupt(self);
+        self.set_nonblocking(false)?;
+        return result;
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        return (**self).interrupted();
+    }
+}
+
+fn hex_u32(value: u32) -> String {
+    return value.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    return u32::from_str_radix(text, 16).ok();
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() & 1 != 0 {
+        return None;
+    }
+    return (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect();
+}
+
+// register values are sent as four little-endian bytes
+fn decode_u32(text: &str) -> Option<u32> {
+    let bytes = decode_hex(text)?;
+    if bytes.len() != 4 {
+        return None;
+    }
+    return Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+}
+
+// "addr,length"
+fn parse_range(text: &str) -> Option<(u32, u32)> {
+    let mut parts = text.splitn(2, ',');
+    let address = parse_hex(parts.next()?)?;
+    let length = parse_hex(parts.next()?)?;
+    return Some((address, length));
+}
+
+fn read_register(emu: &Emulator, regnum: usize) -> Option<u32> {
+    return match regnum {
+        0..=7 => Some(emu.get_register(REGISTERS[regnum])),
+        EIP_REGNUM => Some(emu.eip()),
+        EFLAGS_REGNUM => Some(emu.eflags()),
+        10..=15 => Some(0),
+        _ => None
+    };
+}
+
+fn write_register(emu: &mut Emulator, regnum: usize, value: u32) -> bool {
+    match regnum {
+        0..=7 => emu.set_register(REGISTERS[regnum], value),
+        EIP_REGNUM => emu.set_eip(value),
+        EFLAGS_REGNUM => emu.set_eflags(value),
+        10..=15 => {}
+        _ => return false
+    }
+    return true;
+}
+
+fn stop_packet(result: &Result<Option<StopReason>, EmulatorError>) -> String {
+    return match result {
+        Ok(Some(StopReason::Exit(code))) => format!("W{:02x}", code & 0xff),
+        Ok(Some(StopReason::Watchpoint(watchpoint))) => {
+            let name = match watchpoint.kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch"
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, name, watchpoint.address)
+        }
+        Ok(_) => format!("S{:02x}", SIGTRAP),
+        Err(EmulatorError::MemoryOutOfBounds { .. }) => format!("S{:02x}", SIGSEGV),
+        Err(EmulatorError::DivideError { .. }) => format!("S{:02x}", SIGFPE),
+        Err(_) => format!("S{:02x}", SIGILL)
+    };
+}
+
+// Z2-Z4 map onto write, read and access watchpoints
+fn watchpoint(kind: char, address: u32, length: u32) -> Option<Watchpoint> {
+    let kind = match kind {
+        '2' => WatchKind::Write,
+        '3' => WatchKind::Read,
+        '4' => WatchKind::Access,
+        _ => return None
+    };
+    return Some(Watchpoint { address, length, kind });
+}
+
+// Reads one "$data#checksum" packet, acknowledging it. Returns None at end of stream.
+fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Option<String>> {
+    let mut byte = [0u8; 1];
+    loop {
+        // skip acks and anything else between packets
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        stream.read_exact(&mut checksum)?;
+        let expected = std::str::from_utf8(&checksum).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+        let actual = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if expected != Some(actual) {
+            stream.write_all(b"-")?;
+            continue;
+        }
+        stream.write_all(b"+")?;
+        return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+    }
+}
+
+fn write_packet<S: Write>(stream: &mut S, data: &str) -> io::Result<()> {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    write!(stream, "${}#{:02x}", data, checksum)?;
+    return stream.flush();
+}
+
+// Runs until the program stops, or gdb interrupts it and Ok(None) is returned.
+// The guest runs in slices of SLICE instructions with the stream checked in between.
+fn run<S: Connection>(emu: &mut Emulator, stream: &mut S) -> io::Result<Result<Option<StopReason>, EmulatorError>> {
+    let limit = emu.step_limit();
+    emu.set_step_limit(Some(SLICE));
+    let result = loop {
+        match emu.launch() {
+            Ok(StopReason::StepLimit) => {}
+            result => break Ok(result.map(Some))
+        }
+        match stream.interrupted() {
+            Ok(true) => break Ok(Ok(None)),
+            Ok(false) => {}
+            Err(err) => break Err(err)
+        }
+        // the next slice resumes here and would not stop at a breakpoint
+        if emu.at_breakpoint() {
+            break Ok(Ok(Some(StopReason::Breakpoint(emu.eip()))));
+        }
+    };
+    emu.set_step_limit(limit);
+    return result;
+}
+
+// Serves one gdb connection until the program exits or gdb detaches or kills it.
+// Returns the reason the program stopped, or None if it was left running.
+pub fn serve<S: Connection>(emu: &mut Emulator, mut stream: S) -> io::Result<Option<StopReason>> {
+    let mut last_stop = format!("S{:02x}", SIGTRAP);
+    loop {
+        let packet = match read_packet(&mut stream)? {
+            Some(packet) => packet,
+            None => return Ok(None)
+        };
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+
+        let reply = if command == "?" {
+            last_stop.clone()
+        } else if command == "g" {
+            (0..REGISTER_COUNT).map(|i| hex_u32(read_register(emu, i).unwrap_or(0))).collect()
+        } else if command == "G" {
+            let values: Option<Vec<u32>> = (0..args.len() / 8)
+                .map(|i| args.get(i * 8..i * 8 + 8).and_then(decode_u32)).collect();
+            match values {
+                Some(values) if args.len() & 7 == 0 => {
+                    for (i, value) in values.iter().enumerate() {
+                        write_register(emu, i, *value);
+                    }
+                    "OK".to_string()
+                }
+                _ => "E01".to_string()
+            }
+        } else if command == "p" {
+            match parse_hex(args).and_then(|regnum| read_register(emu, regnum as usize)) {
+                Some(value) => hex_u32(value),
+                None => "E01".to_string()
+            }
+        } else if command == "P" {
+            let mut parts = args.splitn(2, '=');
+            let regnum = parts.next().and_then(parse_hex);
+            let value = parts.next().and_then(decode_u32);
+            match (regnum, value) {
+                (Some(regnum), Some(value)) if write_register(emu, regnum as usize, value) => "OK".to_string(),
+                _ => "E01".to_string()
+            }
+        } else if command == "m" {
+            match parse_range(args).map(|(address, length)| emu.memory_slice(address, length)) {
+                Some(Ok(data)) => hex_bytes(data),
+                Some(Err(_)) => "E14".to_string(),
+                None => "E01".to_string()
+            }
+        } else if command == "M" {
+            let mut parts = args.splitn(2, ':');
+            let range = parts.next().and_then(parse_range);
+            let data = parts.next().and_then(decode_hex);
+            match (range, data) {
+                (Some((address, length)), Some(data)) if data.len() == length as usize => {
+                    match emu.load(address, &data) {
+                        Ok(()) => "OK".to_string(),
+                        Err(_) => "E14".to_string()
+                    }
+                }
+                _ => "E01".to_string()
+            }
+        } else if command == "c" || command == "s" {
+            if let Some(address) = parse_hex(args) {
+                emu.set_eip(address);
+            }
+            let result = if command == "c" {
+                run(emu, &mut stream)?
+            } else {
+                emu.step()
+            };
+            let reply = if command == "c" && result == Ok(None) {
+                format!("S{:02x}", SIGINT)
+            } else {
+                stop_packet(&result)
+            };
+            if let Ok(Some(StopReason::Exit(_))) = result {
+                write_packet(&mut stream, &reply)?;
+                return Ok(result.unwrap());
+            }
+            last_stop = reply.clone();
+            reply
+        } else if command == "Z" || command == "z" {
+            let mut parts = args.splitn(3, ',');
+            let kind = parts.next().and_then(|text| text.chars().next());
+            let address = parts.next().and_then(parse_hex);
+            let length = parts.next().and_then(parse_hex);
+            match (kind, address, length) {
+                (Some('0'), Some(address), Some(_)) | (Some('1'), Some(address), Some(_)) => {
+                    if command == "Z" {
+                        emu.add_breakpoint(address);
+                    } else {
+                        emu.remove_breakpoint(address);
+                    }
+                    "OK".to_string()
+                }
+                (Some(kind), Some(address), Some(length)) => match watchpoint(kind, address, length) {
+                    Some(watchpoint) if command == "Z" => {
+                        emu.add_watchpoint(watchpoint);
+                        "OK".to_string()
+                    }
+                    Some(watchpoint) => {
+                        emu.remove_watchpoint(watchpoint);
+                        "OK".to_string()
+                    }
+                    None => String::new()
+                },
+                _ => "E01".to_string()
+            }
+        } else if command == "k" {
+            return Ok(None);
+        } else if command == "D" {
+            write_packet(&mut stream, "OK")?;
+            return Ok(None);
+        } else if packet.starts_with("qSupported") {
+            "PacketSize=4000".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else if command == "H" {
+            "OK".to_string()
+        } else {
+            // unsupported packets get an empty reply
+            String::new()
+        };
+        write_packet(&mut stream, &reply)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Write};
+
+    use super::{poll_interrupt, serve, write_packet, Connection, INTERRUPT, SLICE};
+    use crate::emulator::Register::*;
+    use crate::emulator::{Emulator, StopReason};
+
+    const TEST_MEMSIZE: u32 = 1024;
+
+    // scripted client: the input is read by the stub, the output collected
+    struct Client {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>
+    }
+
+    impl Read for Client {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            return self.input.read(buf);
+        }
+    }
+
+    impl Write for Client {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(data);
+            return Ok(data.len());
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    impl Connection for Client {
+        fn interrupted(&mut self) -> io::Result<bool> {
+            return poll_interrupt(&mut self.input);
+        }
+    }
+
+    // returns the replies with acks and checksums stripped
+    fn session(emu: &mut Emulator, packets: &[&str]) -> (Option<StopReason>, Vec<String>) {
+        let mut input = Vec::new();
+        for packet in packets {
+            write_packet(&mut input, packet).unwrap();
+            input.push(b'+');
+        }
+        return session_bytes(emu, input);
+    }
+
+    fn session_bytes(emu: &mut Emulator, input: Vec<u8>) -> (Option<StopReason>, Vec<String>) {
+        let mut client = Client { input: Cursor::new(input), output: Vec::new() };
+        let reason = serve(emu, &mut client).unwrap();
+        let output = String::from_utf8(client.output).unwrap();
+        let replies = output.split('$').skip(1).map(|p| p.split('#').next().unwrap().to_string()).collect();
+        return (reason, replies);
+    }
+
+    // mov eax,1; mov [0x100],eax; add eax,2; ret
+    const CODE: [u8; 15] = [0xb8, 0x01, 0x00, 0x00, 0x00, 0x89, 0x05, 0x00, 0x01, 0x00, 0x00,
+                            0x83, 0xc0, 0x02, 0xc3];
+
+    fn emulator() -> Emulator {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &CODE).unwrap();
+        return emu;
+    }
+
+    #[test]
+    fn registers() {
+        let mut emu = emulator();
+        emu.set_register(ECX, 0x12345678);
+        let (reason, replies) = session(&mut emu, &["?", "g", "p1", "P0=efbeadde", "p8", "G0000000", "p9"]);
+        assert_eq!(reason, None);
+        assert_eq!(replies[0], "S05");
+        assert_eq!(replies[1].len(), 16 * 8);
+        assert_eq!(&replies[1][8..16], "78563412");
+        assert_eq!(replies[2], "78563412");
+        assert_eq!(replies[3], "OK");
+        assert_eq!(emu.get_register(EAX), 0xdeadbeef);
+        assert_eq!(replies[4], "00000000");
+        assert_eq!(replies[5], "E01");
+        assert_eq!(replies[6], "00000000");
+    }
+
+    #[test]
+    fn memory() {
+        let mut emu = emulator();
+        let (_, replies) = session(&mut emu, &["m0,5", "M200,3:0102ff", "m200,3", "m3ff,2", "M3ff,2:0000"]);
+        assert_eq!(replies, ["b801000000", "OK", "0102ff", "E14", "E14"]);
+        assert_eq!(emu.memory_slice(0x200, 3), Ok(&[1, 2, 0xff][..]));
+    }
+
+    #[test]
+    fn step_and_breakpoint() {
+        let mut emu = emulator();
+        let (reason, replies) = session(&mut emu, &["s", "Z0,b,1", "c", "z0,b,1", "c"]);
+        assert_eq!(replies, ["S05", "OK", "S05", "OK", "W03"]);
+        assert_eq!(reason, Some(StopReason::Exit(3)));
+    }
+
+    #[test]
+    fn watchpoint() {
+        let mut emu = emulator();
+        let (_, replies) = session(&mut emu, &["Z2,100,4", "c", "p8", "z2,100,4", "Z3,100,4", "c"]);
+        assert_eq!(replies[0], "OK");
+        assert_eq!(replies[1], "T05watch:100;");
+        // the write at 0x5 has completed
+        assert_eq!(replies[2], "0b000000");
+        assert_eq!(emu.memory_u32(0x100), Ok(1));
+        assert_eq!(replies[5], "W03");
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // jmp $
+        emu.load(0, &[0xeb, 0xfe]).unwrap();
+        let mut input = Vec::new();
+        write_packet(&mut input, "c").unwrap();
+        input.push(INTERRUPT);
+        input.push(b'+');
+        write_packet(&mut input, "p8").unwrap();
+        input.push(b'+');
+        write_packet(&mut input, "?").unwrap();
+        input.push(b'+');
+        let (reason, replies) = session_bytes(&mut emu, input);
+        assert_eq!(reason, None);
+        assert_eq!(replies, ["S02", "00000000", "S02"]);
+        assert_eq!(emu.instruction_count(), SLICE);
+        assert_eq!(emu.step_limit(), None);
+    }
+}
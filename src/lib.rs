@@ -0,0 +1,12 @@
+#![allow(clippy::needless_return, clippy::upper_case_acronyms, clippy::bool_assert_comparison)]
+// the original tests declare some emulators `mut` that are only read
+#![cfg_attr(test, allow(unused_mut))]
+
+pub mod debugger;
+pub mod elf;
+pub mod emulator;
+pub mod gdbstub;
+pub mod report;
+
+pub use emulator::{Emulator, EmulatorError, Instruction, Register, Register8, Register16, StopReason, TraceLevel,
+                   WatchKind, Watchpoint, MEMORY_SIZE};
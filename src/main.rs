@@ -1,38 +1,269 @@
+#![allow(clippy::needless_return)]
+
 use std::env;
-use std::error::Error;
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::process;
 
-mod emulator;
+use remu386::{debugger, elf, gdbstub, report};
+use remu386::StopReason;
+use remu386::{Emulator, EmulatorError, Register, TraceLevel};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Usage: {} FILE", args[0])
+mod cli;
+
+const TRACE_ENV: &str = "REMU386_TRACE";
+const ERROR_STATUS: i32 = 255;
+const FAILURE_STATUS: i32 = 254;
+const UNFINISHED_STATUS: i32 = 253;
+
+fn usage(program: &str, why: &str) -> ! {
+    eprintln!("{}", why);
+    eprintln!("Usage: {} [OPTIONS] [FILE]", program);
+    eprintln!("{}", cli::USAGE);
+    process::exit(2);
+}
+
+// host-side failures: files, sockets and terminal I/O, not the guest
+fn fail(why: &str) -> ! {
+    eprintln!("{}", why);
+    process::exit(FAILURE_STATUS);
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    return match fs::read(path) {
+        Err(why) => fail(&format!("couldn't open {}: {}", path, why)),
+        Ok(data) => data,
+    };
+}
+
+// Applies --stack, --reg and --entry, then builds the stack at the final
+// ESP, so `--reg esp=` behaves like --stack.
+fn set_up(emu: &mut Emulator, options: &cli::Options, process: bool) -> Result<(), EmulatorError> {
+    if let Some(stack) = options.stack {
+        emu.set_register(Register::ESP, stack);
     }
+    for (reg, value) in options.registers.iter() {
+        emu.set_register(*reg, *value);
+    }
+    if let Some(entry) = options.entry {
+        emu.set_eip(entry);
+    }
+    if process {
+        let name = options.file.as_deref().unwrap_or("");
+        return emu.init_process_stack(&[name], &[]);
+    }
+    return emu.store_exit_address();
+}
 
-    let mut emu = emulator::Emulator::new(emulator::MEMORY_SIZE);
+// writes guest memory, all of it by default, to a raw file
+fn write_raw_dump(emu: &Emulator, path: &str, range: Option<(u32, u32)>) -> Result<(), String> {
+    let (address, length) = range.unwrap_or((0, emu.memory_size()));
+    let data = emu.memory_slice(address, length).map_err(|err| err.to_string())?;
+    return fs::write(path, data).map_err(|why| why.to_string());
+}
 
-    let path = Path::new(&args[1]);
-    let mut f: std::fs::File = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", path.display(), why.description()),
-        Ok(f) => f,
+// Like a Unix shell, only the low 8 bits of the guest's code survive. A run
+// that stopped without the guest finishing must not look like a success.
+fn exit_status(emu: &Emulator, reason: Option<StopReason>) -> i32 {
+    let code = match reason {
+        Some(StopReason::Exit(code)) => code,
+        Some(StopReason::Halt) => emu.get_register(Register::EAX),
+        _ => return UNFINISHED_STATUS,
     };
+    return (code & 0xff) as i32;
+}
+
+// ADDR is a port, HOST:PORT, or a Unix socket path
+fn serve_gdb(emu: &mut Emulator, address: &str) -> io::Result<Option<StopReason>> {
+    if address.contains('/') {
+        return serve_gdb_unix(emu, address);
+    }
+    let address = if address.contains(':') { address.to_string() } else { format!("127.0.0.1:{}", address) };
+    let listener = TcpListener::bind(&address)?;
+    eprintln!("waiting for gdb on {}", address);
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    return gdbstub::serve(emu, stream);
+}
+
+#[cfg(unix)]
+fn serve_gdb_unix(emu: &mut Emulator, path: &str) -> io::Result<Option<StopReason>> {
+    let listener = UnixListener::bind(path)?;
+    eprintln!("waiting for gdb on {}", path);
+    let (stream, _) = listener.accept()?;
+    return gdbstub::serve(emu, stream);
+}
 
-    let size = match f.read(&mut emu.memory) {
-        Err(why) => panic!("couldn't read binary file: {}", why.description()),
-        Ok(size) => size,
+#[cfg(not(unix))]
+fn serve_gdb_unix(_emu: &mut Emulator, path: &str) -> io::Result<Option<StopReason>> {
+    return Err(io::Error::new(io::ErrorKind::Unsupported, format!("Unix sockets are not supported: {}", path)));
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = &args[0];
+
+    let options = match cli::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(why) => usage(program, &why),
     };
-    println!("loaded memory size: {} B", size);
+    let trace_level = match options.trace_level {
+        Some(level) => level,
+        None => match env::var(TRACE_ENV) {
+            Ok(value) => value.parse().unwrap_or_else(|why: String| usage(program, &why)),
+            Err(_) => TraceLevel::Off,
+        },
+    };
+
+    let mut emu = Emulator::new(options.memory_size);
+    emu.set_trace_level(trace_level);
+    emu.set_exit_address(options.exit_address);
+    emu.set_exit_port(options.exit_port);
 
-    let status= match emu.launch() {
-        Ok(_) => 0,
-        Err(err) => {
-            eprintln!("error: {:?}", err);
-            1
+    let mut process = false;
+    if let Some(file) = &options.file {
+        let data = read_file(file);
+        if elf::is_elf(&data) {
+            if let Err(why) = elf::load(&mut emu, &data) {
+                fail(&format!("couldn't load {}: {}", file, why));
+            }
+            process = true;
+        } else if let Err(why) = emu.load(options.load_address, &data) {
+            fail(&format!("couldn't load {}: {}", file, why));
+        } else {
+            emu.set_eip(options.load_address);
+            emu.set_program_break(options.load_address.saturating_add(data.len() as u32));
         }
+        println!("loaded memory size: {} B", data.len());
+    }
+    for (path, address) in options.loads.iter() {
+        let data = read_file(path);
+        if let Err(why) = emu.load(*address, &data) {
+            fail(&format!("couldn't load {}: {}", path, why));
+        }
+        println!("loaded {} B at {:#010X}", data.len(), address);
+    }
+
+    if let Err(why) = set_up(&mut emu, &options, process) {
+        fail(&format!("couldn't set up the stack: {}", why));
+    }
+
+    let result = if let Some(address) = &options.gdb {
+        match serve_gdb(&mut emu, address) {
+            Ok(reason) => Ok(reason),
+            Err(why) => fail(&format!("gdb connection failed: {}", why)),
+        }
+    } else if options.debug {
+        let stdin = io::stdin();
+        match debugger::run(&mut emu, stdin.lock(), io::stdout()) {
+            Ok(reason) => Ok(reason),
+            Err(why) => fail(&format!("debugger I/O failed: {}", why)),
+        }
+    } else {
+        emu.launch().map(Some)
     };
+    match &result {
+        Ok(Some(reason)) => println!("stop: {:?}", reason),
+        Ok(None) => println!("detached"),
+        Err(err) => eprintln!("error: {}", err),
+    }
     emu.dump_register();
-    emu.dump_memory();
+    if options.dumps.is_empty() {
+        emu.dump_memory();
+    }
+    for (address, length) in options.dumps.iter() {
+        match report::hexdump(&emu, *address, *length) {
+            Ok(text) => print!("{}", text),
+            Err(err) => eprintln!("couldn't dump {:08X}: {}", address, err),
+        }
+    }
+    // a failed dump still lets the others and the JSON be written
+    let mut dump_failed = false;
+    for (path, range) in options.raw_dumps.iter() {
+        if let Err(why) = write_raw_dump(&emu, path, *range) {
+            eprintln!("couldn't write {}: {}", path, why);
+            dump_failed = true;
+        }
+    }
+    if let Some(path) = &options.json {
+        if let Err(why) = fs::write(path, report::json(&emu, &result, &options.json_memory)) {
+            fail(&format!("couldn't write {}: {}", path, why));
+        }
+    }
+    if dump_failed {
+        process::exit(FAILURE_STATUS);
+    }
+    process::exit(match result {
+        Ok(reason) => exit_status(&emu, reason),
+        Err(_) => ERROR_STATUS,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{cli, exit_status, set_up, write_raw_dump, UNFINISHED_STATUS};
+    use remu386::{Emulator, Register, StopReason};
+
+    const TEST_MEMSIZE: u32 = 1024;
+
+    fn options(args: &[&str]) -> cli::Options {
+        // the FILE is only named, not read
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        args.push("a.out".to_string());
+        return cli::parse(&args).unwrap();
+    }
+
+    #[test]
+    fn register_stack() {
+        // mov eax,7; ret
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0xb8, 0x07, 0x00, 0x00, 0x00, 0xc3]).unwrap();
+        emu.load(0x200, &[0xff; 4]).unwrap();
+        set_up(&mut emu, &options(&["--reg", "esp=0x200", "--reg", "eax=1"]), false).unwrap();
+        assert_eq!(emu.get_register(Register::ESP), 0x200);
+        assert_eq!(emu.get_register(Register::EAX), 1);
+        assert_eq!(emu.memory_u32(0x200), Ok(0));
+        assert_eq!(emu.launch(), Ok(StopReason::Exit(7)));
+
+        // --reg esp= overrides --stack, and the process stack is built below it
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        set_up(&mut emu, &options(&["--stack", "0x300", "--reg", "esp=0x200"]), true).unwrap();
+        let esp = emu.get_register(Register::ESP);
+        assert!(esp < 0x204);
+        assert_eq!(emu.memory_u32(esp), Ok(1));
+    }
+
+    #[test]
+    fn exit_statuses() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.set_register(Register::EAX, 0x1234);
+        assert_eq!(exit_status(&emu, Some(StopReason::Exit(0x101))), 1);
+        assert_eq!(exit_status(&emu, Some(StopReason::Halt)), 0x34);
+        assert_eq!(exit_status(&emu, Some(StopReason::Breakpoint(0))), UNFINISHED_STATUS);
+        assert_eq!(exit_status(&emu, Some(StopReason::StepLimit)), UNFINISHED_STATUS);
+        assert_eq!(exit_status(&emu, None), UNFINISHED_STATUS);
+    }
+
+    #[test]
+    fn raw_dump() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0x100, b"remu").unwrap();
+        let path = env::temp_dir().join(format!("remu386-raw-dump-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(write_raw_dump(&emu, path, Some((0x100, 4))), Ok(()));
+        assert_eq!(fs::read(path).unwrap(), b"remu");
+        assert_eq!(write_raw_dump(&emu, path, None), Ok(()));
+        assert_eq!(fs::read(path).unwrap().len(), TEST_MEMSIZE as usize);
+        fs::remove_file(path).unwrap();
+
+        // a directory that doesn't exist, and a range outside memory
+        assert!(write_raw_dump(&emu, &format!("{}/missing/dump.bin", path), None).is_err());
+        assert!(write_raw_dump(&emu, path, Some((0x3ff, 2))).unwrap_err().contains("out of bounds"));
+    }
 }
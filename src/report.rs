@@ -0,0 +1,174 @@
+use std::fmt::Write;
+
+use crate::emulator::flags::{AF, CF, DF, OF, PF, SF, ZF};
+use crate::emulator::{Emulator, EmulatorError, Register, StopReason, Watchpoint};
+use crate::emulator::Register::*;
+
+const REGISTERS: [(&str, Register); 8] = [
+    ("EAX", EAX), ("ECX", ECX), ("EDX", EDX), ("EBX", EBX),
+    ("ESP", ESP), ("EBP", EBP), ("ESI", ESI), ("EDI", EDI)
+];
+
+const FLAGS: [(&str, u32); 7] = [
+    ("CF", CF), ("PF", PF), ("AF", AF), ("ZF", ZF), ("SF", SF), ("DF", DF), ("OF", OF)
+];
+
+// a JSON string literal
+fn string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+fn watchpoint(watchpoint: &Watchpoint) -> String {
+    return format!("{{\"address\": {}, \"length\": {}, \"kind\": {}}}", watchpoint.address,
+                   watchpoint.length, string(&format!("{:?}", watchpoint.kind).to_lowercase()));
+}
+
+fn stop(result: &Result<Option<StopReason>, EmulatorError>) -> String {
+    return match result {
+        Ok(Some(StopReason::Exit(code))) => format!("{{\"reason\": \"exit\", \"code\": {}}}", code),
+        Ok(Some(StopReason::Halt)) => "{\"reason\": \"halt\"}".to_string(),
+        Ok(Some(StopReason::Breakpoint(address))) => {
+            format!("{{\"reason\": \"breakpoint\", \"address\": {}}}", address)
+        }
+        Ok(Some(StopReason::Watchpoint(hit))) => {
+            format!("{{\"reason\": \"watchpoint\", \"watchpoint\": {}}}", watchpoint(hit))
+        }
+        Ok(Some(StopReason::StepLimit)) => "{\"reason\": \"step_limit\"}".to_string(),
+        Ok(None) => "{\"reason\": \"detached\"}".to_string(),
+        Err(err) => format!("{{\"reason\": \"error\", \"message\": {}}}", string(&err.to_string()))
+    };
+}
+
+// Renders the final CPU state, the stop reason and the given memory ranges
+// (address, length) as a JSON object. Memory is a hex string per range.
+pub fn json(emu: &Emulator, result: &Result<Option<StopReason>, EmulatorError>, ranges: &[(u32, u32)]) -> String {
+    let mut out = String::from("{\n");
+    writeln!(out, "  \"stop\": {},", stop(result)).unwrap();
+    writeln!(out, "  \"instruction_count\": {},", emu.instruction_count()).unwrap();
+
+    let mut registers: Vec<String> = REGISTERS.iter()
+        .map(|(name, reg)| format!("\"{}\": {}", name, emu.get_register(*reg)))
+        .collect();
+    registers.push(format!("\"EIP\": {}", emu.eip()));
+    writeln!(out, "  \"registers\": {{{}}},", registers.join(", ")).unwrap();
+
+    let mut flags = vec![format!("\"value\": {}", emu.eflags())];
+    for (name, mask) in FLAGS.iter() {
+        flags.push(format!("\"{}\": {}", name, emu.eflags() & mask != 0));
+    }
+    writeln!(out, "  \"eflags\": {{{}}},", flags.join(", ")).unwrap();
+
+    let memory: Vec<String> = ranges.iter().map(|(address, length)| {
+        let content = match emu.memory_slice(*address, *length) {
+            Ok(data) => {
+                let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("\"bytes\": {}", string(&hex))
+            }
+            Err(err) => format!("\"error\": {}", string(&err.to_string()))
+        };
+        return format!("    {{\"address\": {}, \"length\": {}, {}}}", address, length, content);
+    }).collect();
+    if memory.is_empty() {
+        out.push_str("  \"memory\": []\n");
+    } else {
+        writeln!(out, "  \"memory\": [\n{}\n  ]", memory.join(",\n")).unwrap();
+    }
+    out.push_str("}\n");
+    return out;
+}
+
+// A canonical hexdump of the range: 16 bytes per line with an ASCII column.
+pub fn hexdump(emu: &Emulator, address: u32, length: u32) -> Result<String, EmulatorError> {
+    let data = emu.memory_slice(address, length)?;
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(out, "{:08X} ", address.wrapping_add(16 * i as u32)).unwrap();
+        for column in 0..16 {
+            if column == 8 {
+                out.push(' ');
+            }
+            match line.get(column) {
+                Some(byte) => write!(out, " {:02X}", byte).unwrap(),
+                None => out.push_str("   ")
+            }
+        }
+        let ascii: String = line.iter()
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+            .collect();
+        writeln!(out, "  |{}|", ascii).unwrap();
+    }
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hexdump, json, string};
+    use crate::emulator::Register::*;
+    use crate::emulator::{Emulator, StopReason};
+
+    const TEST_MEMSIZE: u32 = 1024;
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+
+    #[test]
+    fn final_state() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        // mov eax,0x1f; cmp eax,eax; ret
+        emu.load(0, &[0xb8, 0x1f, 0x00, 0x00, 0x00, 0x39, 0xc0, 0xc3]).unwrap();
+        emu.load(0x100, b"hi").unwrap();
+        let result = emu.launch().map(Some);
+        assert_eq!(result, Ok(Some(StopReason::Exit(0x1f))));
+        emu.set_register(EBX, 0xffffffff);
+
+        let text = json(&emu, &result, &[(0x100, 3), (0x3ff, 2)]);
+        assert!(text.starts_with("{\n  \"stop\": {\"reason\": \"exit\", \"code\": 31},\n"));
+        assert!(text.contains("\n  \"instruction_count\": 3,\n"));
+        assert!(text.contains("\"registers\": {\"EAX\": 31, \"ECX\": 0, \"EDX\": 0, \"EBX\": 4294967295, \
+                               \"ESP\": 1024, \"EBP\": 0, \"ESI\": 0, \"EDI\": 0, \"EIP\": 8}"));
+        assert!(text.contains("\"eflags\": {\"value\": 68, \"CF\": false, \"PF\": true, \"AF\": false, \
+                               \"ZF\": true, \"SF\": false, \"DF\": false, \"OF\": false}"));
+        assert!(text.contains("    {\"address\": 256, \"length\": 3, \"bytes\": \"686900\"},\n"));
+        assert!(text.contains("    {\"address\": 1023, \"length\": 2, \"error\": \"memory access out of bounds"));
+        assert!(text.ends_with("  ]\n}\n"));
+    }
+
+    #[test]
+    fn hexdump_lines() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0x100, b"Hello, world!\n\x00\xffremu").unwrap();
+        assert_eq!(hexdump(&emu, 0x100, 20).unwrap(),
+                   "00000100  48 65 6C 6C 6F 2C 20 77  6F 72 6C 64 21 0A 00 FF  |Hello, world!...|\n\
+                    00000110  72 65 6D 75                                       |remu|\n");
+        assert_eq!(hexdump(&emu, 0, 0).unwrap(), "");
+        assert!(hexdump(&emu, 0x3f0, 0x20).is_err());
+    }
+
+    #[test]
+    fn error_and_detach() {
+        let mut emu = Emulator::new(TEST_MEMSIZE);
+        emu.load(0, &[0x0f, 0xff]).unwrap();
+        let result = emu.launch().map(Some);
+        let text = json(&emu, &result, &[]);
+        assert!(text.contains("\"stop\": {\"reason\": \"error\", \
+                               \"message\": \"unknown opcode at 00000000: 0F FF\"}"));
+        assert!(text.contains("\"memory\": []\n}"));
+
+        let text = json(&emu, &Ok(None), &[]);
+        assert!(text.contains("\"stop\": {\"reason\": \"detached\"}"));
+    }
+}